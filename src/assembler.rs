@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::instructions::{Instruction, ParseError, SpannedError};
+use crate::instructions::set_imms::LoadImmediate;
+use crate::conditionals::Conditionals;
+use crate::data;
+use crate::lexer::Span;
+use crate::parser::{ConstantNode, Statement, StatementKind};
+use crate::preprocessor::{source_lines, Preprocessor, SourceLine};
+use crate::repeat::repeat;
+use crate::program::{Line, Program, Word};
+use crate::symbols::{Context, SymbolKind, SymbolTable};
+
+/// Settings for [`assemble_with`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Path of the source being assembled. Files it `.include`s are looked up next to it,
+    /// or in the current directory without a path.
+    pub path: Option<PathBuf>,
+    /// Directories searched for included files that are not next to the file including them.
+    pub include_paths: Vec<PathBuf>,
+    /// Word written into the gaps left by `.org` and `.align`.
+    pub fill: [u8; 4],
+    /// Constants defined before the first line, like `-D NAME=VALUE` on the command line. A name given more
+    /// than once takes the last value.
+    pub defines: Vec<(String, i64)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self { path: None, include_paths: Vec::new(), fill: Instruction::Nop.encode(), defines: Vec::new() }
+    }
+}
+
+/// Assembles Sopt source into a [`Program`] with the default [`Options`].
+pub fn assemble(source: &str) -> Result<Program, Diagnostics> {
+    assemble_with(source, &Options::default())
+}
+
+/// Assembles Sopt source into a [`Program`].
+///
+/// The first pass picks the lines to assemble from `.if` blocks, expands the macros and included files in them,
+/// repeats `.rep` and `.for` blocks, assigns addresses to labels and evaluates constants, the
+/// second one lowers instructions and data now that every label is known. `.org` and `.align` gaps are
+/// filled with [`Options::fill`].
+///
+/// Whatever the first pass needs right away, like a constant, a condition, the count of `.zero` or `.rep`,
+/// the bounds of `.for` or the address of `.org`, can only use symbols defined above it, since the labels
+/// below do not have their addresses yet. The size of an `LI` is fixed in
+/// the first pass, so a value that is only known later always takes two words. A bad line
+/// does not stop either pass, so the returned [`Diagnostics`] hold every
+/// problem in the source.
+pub fn assemble_with(source: &str, options: &Options) -> Result<Program, Diagnostics> {
+    let mut diagnostics = Diagnostics::new();
+    let mut symbols = SymbolTable::new();
+    let mut conditionals = Conditionals::new();
+    let mut lines: Vec<(SourceLine, u32, u32)> = Vec::new();
+    let mut address = 0;
+    let mut repeated = 0;
+    // Whether a line went past the last address, after which no line is given any words.
+    let mut full = false;
+
+    // A name defined more than once gets its last value, like repeating `-D` on the command line.
+    for (index, (name, value)) in options.defines.iter().enumerate() {
+        if options.defines[index + 1..].iter().any(|(later, _)| later == name) {
+            continue;
+        }
+        let span = Span { start: 0, end: 0, line: 0, column: 0 };
+        symbols.define(name, SymbolKind::Constant, *value, span).expect("duplicate defines were skipped");
+    }
+
+    let mut preprocessor = Preprocessor::new(options);
+    let mut queue = VecDeque::from(source_lines(source, None, &[]));
+    while let Some(mut line) = queue.pop_front() {
+        let line_address = address;
+
+        if let Ok(Statement { kind: StatementKind::Conditional(node), span, .. }) = &line.statement {
+            if let Err(err) = conditionals.directive(node, *span, lines.len(), &line.expansions, &symbols) {
+                diagnostics.push(diagnostic(&line, err));
+            }
+            lines.push((line, address, 0));
+            continue;
+        }
+        if !conditionals.active() {
+            // Lines that are left out stay in the program as empty statements, so they still show up in the listing.
+            let span = Span { start: 0, end: 0, line: line.number, column: 1 };
+            line.statement = Ok(Statement { label: None, kind: StatementKind::Empty, span });
+            lines.push((line, address, 0));
+            continue;
+        }
+        preprocessor.expand(&mut line, &mut queue);
+
+        let size = match &line.statement {
+            Ok(statement) => {
+                // `.org` and `.align` move the address first, so a label in front of them names the new address.
+                let padding = match &statement.kind {
+                    StatementKind::Layout(node) => data::padding(node, &Context { symbols: &symbols, address }).unwrap_or_else(|err| {
+                        diagnostics.push(diagnostic(&line, err));
+                        0
+                    }),
+                    _ => 0,
+                };
+                if let Some(label) = &statement.label {
+                    if let Err(err) = symbols.define(&label.name, SymbolKind::Label, i64::from(address + padding), label.span) {
+                        diagnostics.push(diagnostic(&line, err));
+                    }
+                }
+                match &statement.kind {
+                    StatementKind::Instruction(_) => 1,
+                    StatementKind::LoadImmediate(node) => LoadImmediate::size(symbols.value(&node.value).ok().flatten()),
+                    StatementKind::Constant(constant) => {
+                        if let Err(err) = define_constant(&mut symbols, constant) {
+                            diagnostics.push(diagnostic(&line, err));
+                        }
+                        0
+                    }
+                    StatementKind::Data(node) => {
+                        let context = Context { symbols: &symbols, address };
+                        data::size(node, &context).unwrap_or_else(|err| {
+                            diagnostics.push(diagnostic(&line, err));
+                            0
+                        })
+                    }
+                    StatementKind::Layout(_) => padding,
+                    StatementKind::Repeat(node) => {
+                        let context = Context { symbols: &symbols, address };
+                        if let Err(err) = repeat(node, &line, statement.span, &mut queue, &mut repeated, &mut preprocessor, &context) {
+                            diagnostics.push(diagnostic(&line, err));
+                        }
+                        0
+                    }
+                    StatementKind::RepeatEnd => {
+                        diagnostics.push(diagnostic(&line, ParseError::UnmatchedEndr.at(statement.span)));
+                        0
+                    }
+                    StatementKind::Empty | StatementKind::MacroDefinition(_) | StatementKind::MacroEnd | StatementKind::MacroCall(_)
+                    | StatementKind::Include(_) | StatementKind::Conditional(_) => 0,
+                }
+            }
+            Err(err) => {
+                diagnostics.push(diagnostic(&line, err.clone()));
+                // Assume the broken line was an instruction so the labels after it keep their addresses.
+                1
+            }
+        };
+        let size = match address.checked_add(size) {
+            _ if full => 0,
+            Some(end) if i64::from(end) <= data::MAX_ADDRESS + 1 => size,
+            _ => {
+                let span = line.statement.as_ref().map_or(Span { start: 0, end: 0, line: line.number, column: 1 }, |statement| statement.span);
+                diagnostics.push(diagnostic(&line, ParseError::ProgramTooLarge((data::MAX_ADDRESS + 1) as u32).at(span)));
+                full = true;
+                0
+            }
+        };
+        address += size;
+        lines.push((line, line_address, size));
+    }
+
+    for (index, err) in conditionals.finish() {
+        diagnostics.push(diagnostic(&lines[index].0, err));
+    }
+
+    let mut program = Program::default();
+
+    for (line, address, size) in lines {
+        let context = Context { symbols: &symbols, address };
+        let (words, pseudo) = match &line.statement {
+            Ok(Statement { kind: StatementKind::Instruction(node), .. }) => {
+                (Instruction::lower(node.mnemonic, &node.operands, &context).map(|instruction| vec![Word::Instruction(instruction)]), false)
+            }
+            Ok(Statement { kind: StatementKind::LoadImmediate(node), .. }) => {
+                let words = LoadImmediate::parse(node, &context).map(|load| load.expand(size).into_iter().map(Word::Instruction).collect());
+                (words, true)
+            }
+            Ok(Statement { kind: StatementKind::Data(node), .. }) => (data::lower(node, size, &context), false),
+            Ok(Statement { kind: StatementKind::Layout(_), .. }) => (Ok(vec![Word::Data(options.fill); size as usize]), false),
+            _ => (Ok(Vec::new()), false),
+        };
+        let words = words.unwrap_or_else(|err| {
+            diagnostics.push(diagnostic(&line, err));
+            Vec::new()
+        });
+        let label = line.statement.as_ref().ok().and_then(|statement| statement.label.as_ref()).map(|label| label.name.clone());
+        program.lines.push(Line { file: line.file, number: line.number, text: line.text, address, label, words, pseudo, expansions: line.expansions, renames: line.renames });
+    }
+
+    if !diagnostics.is_empty() {
+        diagnostics.sort();
+        if let Some(path) = &options.path {
+            diagnostics.set_source_path(&path.display().to_string());
+        }
+        return Err(diagnostics);
+    }
+
+    program.symbols = symbols;
+    Ok(program)
+}
+
+/// Evaluates a constant's value and adds it to `symbols`.
+fn define_constant(symbols: &mut SymbolTable, constant: &ConstantNode) -> Result<(), SpannedError> {
+    let value = symbols.value(&constant.value)?
+        .ok_or_else(|| ParseError::InvalidConstantValue(constant.value.text.clone()).at(constant.value.span))?;
+    symbols.define(&constant.name, SymbolKind::Constant, value, constant.name_span)
+}
+
+fn diagnostic(line: &SourceLine, err: SpannedError) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        file: line.file.clone(),
+        line: line.number,
+        text: line.text.clone(),
+        help: err.help.or_else(|| err.error.help().map(str::to_owned)),
+        error: err.error,
+        span: err.span,
+        opcode: err.mnemonic.map(|mnemonic| mnemonic.opcode()),
+        expansions: line.expansions.clone(),
+    }
+    .written(&line.renames)
+}
@@ -0,0 +1,105 @@
+use crate::instructions::{ParseError, SpannedError};
+use crate::lexer::Span;
+use crate::parser::ConditionalNode;
+use crate::preprocessor::Expansion;
+use crate::symbols::SymbolTable;
+
+/// An `.if`, `.ifdef` or `.ifndef` block that has not reached its `.endif` yet.
+#[derive(Debug, Clone)]
+struct Block {
+    /// The opening directive, like `.ifdef`, and its span.
+    directive: &'static str,
+    span: Span,
+    /// Index of the opening line in the program, to report a missing `.endif`.
+    line: usize,
+    /// Macro invocations and includes of the opening line. The block has to end in the same place.
+    expansions: Vec<Expansion>,
+    /// Whether the lines around the block are assembled.
+    outer: bool,
+    /// Whether one of the branches so far was chosen, so the rest are left out.
+    taken: bool,
+    /// Whether the lines of the current branch are assembled.
+    active: bool,
+    after_else: bool,
+}
+
+/// The conditional blocks around the line being assembled.
+///
+/// Conditions are evaluated in the first pass of [`assemble_with`](crate::assembler::assemble_with).
+/// Conditions in blocks that are left out are not evaluated at all.
+#[derive(Debug, Clone, Default)]
+pub struct Conditionals {
+    blocks: Vec<Block>,
+}
+
+impl Conditionals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether lines at this point are assembled.
+    pub fn active(&self) -> bool {
+        self.blocks.last().is_none_or(|block| block.active)
+    }
+
+    /// Opens, switches or closes a block for the directive `node`, found on line `line` of the program.
+    pub fn directive(&mut self, node: &ConditionalNode, span: Span, line: usize, expansions: &[Expansion], symbols: &SymbolTable) -> Result<(), SpannedError> {
+        let directive = node.directive();
+        match node {
+            ConditionalNode::If(_) | ConditionalNode::IfDefined { .. } => {
+                let outer = self.active();
+                let value = if outer { condition(node, symbols) } else { Ok(false) };
+                let active = *value.as_ref().unwrap_or(&false);
+                // A block whose condition is broken leaves out all of its branches.
+                let taken = active || value.is_err();
+                self.blocks.push(Block { directive, span, line, expansions: expansions.to_vec(), outer, taken, active, after_else: false });
+                value.map(|_| ())
+            }
+            ConditionalNode::Elif(_) | ConditionalNode::Else => {
+                let block = self.current(directive, span, expansions)?;
+                if block.after_else {
+                    return Err(ParseError::ConditionalAfterElse(directive.to_owned()).at(span));
+                }
+                let value = if block.outer && !block.taken { condition(node, symbols) } else { Ok(false) };
+                block.active = *value.as_ref().unwrap_or(&false);
+                block.taken |= block.active || value.is_err();
+                block.after_else = *node == ConditionalNode::Else;
+                value.map(|_| ())
+            }
+            ConditionalNode::Endif => {
+                self.current(directive, span, expansions)?;
+                self.blocks.pop();
+                Ok(())
+            }
+        }
+    }
+
+    /// Errors for the blocks that were never closed, each with the index of its opening line.
+    pub fn finish(self) -> Vec<(usize, SpannedError)> {
+        self.blocks
+            .into_iter()
+            .map(|block| (block.line, ParseError::UnterminatedConditional(block.directive.to_owned()).at(block.span)))
+            .collect()
+    }
+
+    /// The innermost block, which `directive` continues if it was opened in the same file or macro.
+    fn current(&mut self, directive: &str, span: Span, expansions: &[Expansion]) -> Result<&mut Block, SpannedError> {
+        match self.blocks.last_mut() {
+            Some(block) if block.expansions == expansions => Ok(block),
+            _ => Err(ParseError::UnmatchedConditional(directive.to_owned()).at(span)),
+        }
+    }
+}
+
+/// Whether the branch started by `node` is taken. `.else` always is.
+fn condition(node: &ConditionalNode, symbols: &SymbolTable) -> Result<bool, SpannedError> {
+    match node {
+        ConditionalNode::If(condition) | ConditionalNode::Elif(condition) => symbols
+            .value(condition)?
+            .map(|value| value != 0)
+            .ok_or_else(|| ParseError::InvalidCondition(condition.text.clone()).at(condition.span)),
+        ConditionalNode::IfDefined { name, defined } => Ok(symbols.get(name).is_some() == *defined),
+        ConditionalNode::Else => Ok(true),
+        ConditionalNode::Endif => unreachable!("`.endif` has no condition"),
+    }
+}
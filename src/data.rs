@@ -0,0 +1,71 @@
+use crate::instructions::helpers::signed_immediate;
+use crate::instructions::{ParseError, Slot, SpannedError};
+use crate::parser::{DataNode, LayoutNode, Operand};
+use crate::program::Word;
+use crate::symbols::Context;
+
+/// Most words a single `.zero` may reserve, as many as 16-bit addresses can reach.
+pub const MAX_ZERO_WORDS: i64 = 65536;
+
+/// Highest address `.org` may move to and largest `.align` boundary, and the last address a word can be placed at.
+pub const MAX_ADDRESS: i64 = 65535;
+
+/// Number of words `node` takes, needed in the first pass of [`assemble_with`](crate::assembler::assemble_with).
+pub fn size(node: &DataNode, context: &Context) -> Result<u32, SpannedError> {
+    Ok(match node {
+        DataNode::Words(values) => values.len() as u32,
+        DataNode::Bytes(bytes) => bytes.len().div_ceil(4) as u32,
+        DataNode::Ascii(text) => text.len().div_ceil(4) as u32,
+        DataNode::Zero(count) => signed_immediate(count, Slot::Imm1, 0, MAX_ZERO_WORDS, context)? as u32,
+    })
+}
+
+/// The data words of `node`, given the `size` it was assigned in the first pass. Bytes fill each
+/// word from its most significant byte and the last word is padded with zeros.
+pub fn lower(node: &DataNode, size: u32, context: &Context) -> Result<Vec<Word>, SpannedError> {
+    match node {
+        DataNode::Words(values) => values
+            .iter()
+            .map(|value| {
+                let value = signed_immediate(value, Slot::Imm1, -(1 << 31), i64::from(u32::MAX), context)?;
+                Ok(Word::Data((value as u32).to_be_bytes()))
+            })
+            .collect(),
+        DataNode::Bytes(bytes) => Ok(pack(&bytes.iter().map(|byte| byte_value(byte, context)).collect::<Result<Vec<u8>, _>>()?)),
+        DataNode::Ascii(text) => Ok(pack(text.as_bytes())),
+        DataNode::Zero(_) => Ok(vec![Word::Data([0; 4]); size as usize]),
+    }
+}
+
+/// Number of fill words `node` needs to reach its address from `context.address`, like [`size`] in the first pass.
+pub fn padding(node: &LayoutNode, context: &Context) -> Result<u32, SpannedError> {
+    match node {
+        LayoutNode::Org(target) => {
+            let address = signed_immediate(target, Slot::Imm1, 0, MAX_ADDRESS, context)?;
+            if address < i64::from(context.address) {
+                return Err(ParseError::OrgOverlap(target.text.clone(), address, context.address).at(target.span));
+            }
+            Ok(address as u32 - context.address)
+        }
+        LayoutNode::Align(boundary) => {
+            let boundary = signed_immediate(boundary, Slot::Imm1, 1, MAX_ADDRESS + 1, context)? as u32;
+            Ok(context.address.next_multiple_of(boundary) - context.address)
+        }
+    }
+}
+
+/// A byte is written as a number from -128 to 255, negative ones in two's complement.
+fn byte_value(operand: &Operand, context: &Context) -> Result<u8, SpannedError> {
+    signed_immediate(operand, Slot::Imm1, -128, 255, context).map(|byte| byte as u8)
+}
+
+fn pack(bytes: &[u8]) -> Vec<Word> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            Word::Data(word)
+        })
+        .collect()
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use colored::{Color, Colorize};
+use crate::instructions::ParseError;
+use crate::json;
+use crate::lexer::Span;
+use crate::preprocessor::{substitute, Expansion};
+
+/// Whether a diagnostic stops the program from being assembled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Something that assembles but is probably a mistake, found by the [lints](crate::lints).
+    Warning,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// A problem found while assembling one source line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// File the line is in, `None` for a main source that was not read from a file.
+    pub file: Option<String>,
+    /// 1-based line number in its file.
+    pub line: usize,
+    /// The source line as written, with macro arguments substituted.
+    pub text: String,
+    pub error: ParseError,
+    /// Exact part of the source the error points at.
+    pub span: Span,
+    /// Opcode of the instruction the line was recognised as, if any.
+    pub opcode: Option<u8>,
+    /// Advice on fixing the problem, like a name that was probably meant.
+    pub help: Option<String>,
+    /// Macro invocations and includes the line was expanded from, innermost first.
+    pub expansions: Vec<Expansion>,
+}
+
+impl Diagnostic {
+    /// Line of the source the problem comes from, the outermost invocation for expanded lines.
+    pub fn root_line(&self) -> usize {
+        self.expansions.last().map_or(self.line, |expansion| expansion.line)
+    }
+
+    /// The diagnostic with the labels renamed in macro expansions and `.rep` and `.for` iterations shown the
+    /// way they were written, given `renames` from the renamed labels to the written ones.
+    pub fn written(self, renames: &HashMap<String, String>) -> Diagnostic {
+        if renames.is_empty() {
+            return self;
+        }
+        // The span moves and widens with the names before and inside it.
+        let start = self.span.column.saturating_sub(1).min(self.text.len());
+        let end = (start + self.span.end.saturating_sub(self.span.start)).min(self.text.len());
+        let span = match (self.text.get(..start), self.text.get(start..end)) {
+            (Some(before), Some(inside)) => {
+                let column = substitute(before, renames).len() + 1;
+                Span { end: self.span.start + substitute(inside, renames).len(), column, ..self.span }
+            }
+            _ => self.span,
+        };
+        let expansions = self
+            .expansions
+            .into_iter()
+            .map(|expansion| Expansion { text: substitute(&expansion.text, renames), ..expansion })
+            .collect();
+        Diagnostic {
+            text: substitute(&self.text, renames),
+            error: self.error.rename(renames),
+            span,
+            help: self.help.map(|help| substitute(&help, renames)),
+            expansions,
+            ..self
+        }
+    }
+
+    /// The diagnostic as a single line JSON object, for editors and other tools.
+    ///
+    /// Columns are 1-based byte columns in the line, `column_end` being one past the last one. `expected` is
+    /// the range an out of range operand may take, `opcode` the instruction the line was recognised as, and
+    /// `expansions` the lines the line was expanded from, innermost first. Fields without a value are `null`.
+    pub fn to_json(&self) -> String {
+        let column = self.span.column.max(1);
+        let width = self.span.end.saturating_sub(self.span.start);
+        let start = (column - 1).min(self.text.len());
+        let text = self.text.get(start..(start + width).min(self.text.len())).unwrap_or("");
+        let expected = self.error.expected().map_or("null".to_owned(), |(min, max)| format!("{{\"min\": {}, \"max\": {}}}", min, max));
+        let expansions: Vec<String> = self
+            .expansions
+            .iter()
+            .map(|expansion| {
+                let file = expansion.file.as_deref().map_or("null".to_owned(), json::string);
+                format!("{{\"message\": {}, \"file\": {}, \"line\": {}, \"text\": {}}}", json::string(&expansion.to_string()), file, expansion.line, json::string(&expansion.text))
+            })
+            .collect();
+
+        format!(
+            "{{\"severity\": {}, \"code\": {}, \"message\": {}, \"file\": {}, \"line\": {}, \"column_start\": {}, \"column_end\": {}, \"text\": {}, \"source\": {}, \"expected\": {}, \"opcode\": {}, \"help\": {}, \"expansions\": [{}]}}",
+            json::string(self.severity.name()),
+            json::string(self.error.code()),
+            json::string(&self.error.to_string()),
+            self.file.as_deref().map_or("null".to_owned(), json::string),
+            self.line,
+            column,
+            column + width,
+            json::string(text),
+            json::string(&self.text),
+            expected,
+            self.opcode.map_or("null".to_owned(), |opcode| opcode.to_string()),
+            self.help.as_deref().map_or("null".to_owned(), json::string),
+            expansions.join(", ")
+        )
+    }
+
+    /// Carets under the part of the line the error points at, one for every character of the span and at
+    /// least one. Tabs before it are kept so the carets line up with the text above.
+    fn underline(&self) -> String {
+        let start = self.span.column.saturating_sub(1).min(self.text.len());
+        let end = (start + self.span.end.saturating_sub(self.span.start)).min(self.text.len());
+        let prefix: String = self.text.get(..start).unwrap_or("").chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let width = self.text.get(start..end).map_or(0, |text| text.chars().count()).max(1);
+        format!("{}{}", prefix, "^".repeat(width))
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let file = self.file.as_deref().unwrap_or("<source>");
+        // Wide enough for every line number shown, so all the `|` line up.
+        let gutter = self.expansions.iter().map(|expansion| expansion.line).chain([self.line]).max().unwrap_or(0).to_string().len();
+        let bar = format!("{} |", " ".repeat(gutter)).blue().bold();
+
+        let heading = format!("{}[{}]:", self.severity.name(), self.error.code());
+        let color = match self.severity {
+            Severity::Error => Color::Red,
+            Severity::Warning => Color::Yellow,
+        };
+        writeln!(f, "{} {}", heading.color(color).bold(), self.error.to_string().bold())?;
+        writeln!(f, "{}{} {}:{}:{}", " ".repeat(gutter), "-->".blue().bold(), file, self.line, self.span.column.max(1))?;
+        writeln!(f, "{}", bar)?;
+        writeln!(f, "{} {}", format!("{:>gutter$} |", self.line).blue().bold(), self.text)?;
+        write!(f, "{} {}", bar, self.underline().color(color).bold())?;
+        if let Some(opcode) = self.opcode {
+            write!(f, "\n{} {} instruction found: {:02X}", " ".repeat(gutter), "= note:".bold(), opcode)?;
+        }
+        if let ParseError::Lint(warning) = &self.error {
+            let note = match self.severity {
+                Severity::Error => format!("`{}` was made an error with `--deny`", warning.lint().name()),
+                Severity::Warning => format!("`--allow {}` turns this warning off", warning.lint().name()),
+            };
+            write!(f, "\n{} {} {}", " ".repeat(gutter), "= note:".bold(), note)?;
+        }
+        if let Some(help) = &self.help {
+            write!(f, "\n{} {} {}", " ".repeat(gutter), "= help:".bold(), help)?;
+        }
+
+        // A recursive macro repeats the same invocation many times, so it is shown only once.
+        let mut index = 0;
+        while let Some(expansion) = self.expansions.get(index) {
+            let repeats = self.expansions[index..].iter().take_while(|other| *other == expansion).count();
+            write!(f, "\n{} {}", "note:".bold(), expansion)?;
+            if repeats > 1 {
+                write!(f, " ({} times in a row)", repeats)?;
+            }
+            let file = expansion.file.as_deref().unwrap_or("<source>");
+            write!(f, "\n{}{} {}:{}", " ".repeat(gutter), "-->".blue().bold(), file, expansion.line)?;
+            write!(f, "\n{}\n{} {}", bar, format!("{:>gutter$} |", expansion.line).blue().bold(), expansion.text)?;
+            index += repeats;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem found in a program, in source order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Orders diagnostics by their position in the source, keeping the ones from one macro invocation together.
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_by_key(|diagnostic| (diagnostic.root_line(), diagnostic.line, diagnostic.span.column));
+    }
+
+    /// Names the file of the diagnostics and expansions in the main source, which the assembler only knows when
+    /// given a path.
+    pub fn set_source_path(&mut self, path: &str) {
+        for diagnostic in &mut self.diagnostics {
+            let expansions = diagnostic.expansions.iter_mut().map(|expansion| &mut expansion.file);
+            for file in std::iter::once(&mut diagnostic.file).chain(expansions).filter(|file| file.is_none()) {
+                *file = Some(path.to_owned());
+            }
+        }
+    }
+
+    /// Number of diagnostics with `severity`.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == severity).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Every diagnostic as a JSON object on its own line.
+    pub fn to_json(&self) -> String {
+        self.diagnostics.iter().map(|diagnostic| diagnostic.to_json() + "\n").collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, diagnostic) in self.diagnostics.iter().enumerate() {
+            if index > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -0,0 +1,130 @@
+use std::fmt::{Display, Formatter};
+use crate::instructions::Instruction;
+
+/// Which of the two equivalent Sopt syntaxes to emit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Style {
+    /// `reg1 += reg3 + 99`, `if (reg0 < reg1) pc += 3`, ...
+    #[default]
+    Infix,
+    /// `ADD reg1, reg3, 99`, `LTJUMP reg0, reg1, 3`, ...
+    Mnemonic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisassembleError {
+    /// A `.tik` line that is neither blank, a comment nor four hex bytes.
+    InvalidWord(usize, String),
+}
+
+impl Display for DisassembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisassembleError::InvalidWord(line, text) => write!(f, "line {} is not a 4-byte word: {}", line, text),
+        }
+    }
+}
+
+impl std::error::Error for DisassembleError {}
+
+/// Turns annotated `.tik` text back into `.sop` source.
+///
+/// Blank and comment-only lines are kept, every word is replaced by the
+/// instruction it decodes to and words that are not valid instructions are
+/// emitted as raw `.word` data.
+pub fn disassemble(tik: &str, style: Style) -> Result<String, DisassembleError> {
+    let mut output = String::new();
+
+    for (index, text) in tik.lines().enumerate() {
+        let (code, comment) = match text.split_once(';') {
+            Some((code, comment)) => (code.trim(), Some(comment)),
+            None => (text.trim(), None),
+        };
+
+        if code.is_empty() {
+            if let Some(comment) = comment {
+                output.push_str(&format!(";{}", comment));
+            }
+            output.push('\n');
+            continue;
+        }
+
+        let word = parse_word(code).ok_or_else(|| DisassembleError::InvalidWord(index + 1, text.to_owned()))?;
+        output.push_str(&disassemble_word(word, style));
+        output.push('\n');
+    }
+
+    Ok(output)
+}
+
+/// Source line for a single machine word.
+pub fn disassemble_word(word: [u8; 4], style: Style) -> String {
+    match Instruction::decode(word) {
+        Some(instruction) => to_source(&instruction, style),
+        None => format!(".word 0x{:08X} ; unknown instruction (opcode {:02X})", u32::from_be_bytes(word), word[0]),
+    }
+}
+
+/// Formats `instruction` as Sopt source the assembler turns back into the same word.
+pub fn to_source(instruction: &Instruction, style: Style) -> String {
+    let name = instruction.mnemonic().name();
+
+    match (instruction, style) {
+        (Instruction::Nop, _) => name.to_owned(),
+        (Instruction::Add(reg), Style::Infix) => format!("reg{} += reg{} + {}", reg.reg1, reg.reg2, reg.imm1),
+        (Instruction::Sub(reg), Style::Infix) => format!("reg{} -= reg{} + {}", reg.reg1, reg.reg2, reg.imm1),
+        (Instruction::Mul(reg), Style::Infix) => format!("reg{} *= reg{} + {}", reg.reg1, reg.reg2, reg.imm1),
+        (Instruction::Mov(reg), Style::Infix) => format!("reg{} = reg{} + {}", reg.reg1, reg.reg2, reg.imm1),
+        (Instruction::Add(reg) | Instruction::Sub(reg) | Instruction::Mul(reg) | Instruction::Mov(reg), Style::Mnemonic) => {
+            format!("{} reg{}, reg{}, {}", name, reg.reg1, reg.reg2, reg.imm1)
+        }
+        (Instruction::Load(mem), Style::Infix) => format!("reg{} = {}", mem.reg1, memory(mem.reg2, mem.imm1)),
+        (Instruction::Store(mem), Style::Infix) => format!("{} = reg{}", memory(mem.reg2, mem.imm1), mem.reg1),
+        (Instruction::Load(mem), Style::Mnemonic) => format!("{} reg{}, reg{}, {}", name, mem.reg1, mem.reg2, mem.imm1),
+        (Instruction::Store(mem), Style::Mnemonic) => format!("{} reg{}, {}, reg{}", name, mem.reg2, mem.imm1, mem.reg1),
+        (Instruction::Jump(jump) | Instruction::RevJump(jump) | Instruction::LtJump(jump)
+        | Instruction::RevLtJump(jump) | Instruction::NeqJump(jump) | Instruction::RevNeqJump(jump), Style::Infix) => {
+            let (comparison, operator) = match instruction {
+                Instruction::Jump(_) => ("==", "+="),
+                Instruction::RevJump(_) => ("==", "-="),
+                Instruction::LtJump(_) => ("<", "+="),
+                Instruction::RevLtJump(_) => ("<", "-="),
+                Instruction::NeqJump(_) => ("!=", "+="),
+                _ => ("!=", "-="),
+            };
+            format!("if (reg{} {} reg{}) pc {} {}", jump.reg1, comparison, jump.reg2, operator, jump.imm1)
+        }
+        (Instruction::Jump(jump) | Instruction::RevJump(jump) | Instruction::LtJump(jump)
+        | Instruction::RevLtJump(jump) | Instruction::NeqJump(jump) | Instruction::RevNeqJump(jump), Style::Mnemonic) => {
+            format!("{} reg{}, reg{}, {}", name, jump.reg1, jump.reg2, jump.imm1)
+        }
+        (Instruction::SetImmLow(set_imm), Style::Infix) => format!("reg{}[low] = {}", set_imm.reg1, set_imm.imm1),
+        (Instruction::SetImmHigh(set_imm), Style::Infix) => format!("reg{}[high] = {}", set_imm.reg1, set_imm.imm1),
+        (Instruction::SetImmLow(set_imm) | Instruction::SetImmHigh(set_imm), Style::Mnemonic) => {
+            format!("{} reg{}, {}", name, set_imm.reg1, set_imm.imm1)
+        }
+        (Instruction::Teleport(teleport), _) => format!("{} {}, {}", name, teleport.imm1, teleport.imm2),
+        (Instruction::Bomb(bomb), _) => format!("{} {}", name, bomb.imm1),
+    }
+}
+
+/// `mem[reg2 + imm1]`, shortened to `mem[reg2]` or `mem[imm1]` when one of them is zero.
+fn memory(reg2: u8, imm1: u16) -> String {
+    match (reg2, imm1) {
+        (0, imm1) => format!("mem[{}]", imm1),
+        (reg2, 0) => format!("mem[reg{}]", reg2),
+        (reg2, imm1) => format!("mem[reg{} + {}]", reg2, imm1),
+    }
+}
+
+/// Parses `XX XX XX XX` (hex bytes separated by whitespace) into a word.
+fn parse_word(code: &str) -> Option<[u8; 4]> {
+    let mut word = [0; 4];
+    let mut bytes = code.split_whitespace();
+    for byte in word.iter_mut() {
+        let text = bytes.next()?;
+        if text.len() != 2 { return None; }
+        *byte = u8::from_str_radix(text, 16).ok()?;
+    }
+    bytes.next().is_none().then_some(word)
+}
@@ -0,0 +1,308 @@
+use std::fmt::{Display, Formatter};
+use crate::instructions::Instruction;
+use crate::program::Program;
+
+/// Number of 32-bit words of memory a [`Machine`] gets by default.
+pub const DEFAULT_MEMORY_SIZE: usize = 65536;
+
+/// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// The program counter moved past the last word of the program.
+    EndOfProgram,
+    /// `max_steps` instructions were executed without halting.
+    StepLimit,
+    /// The word at `pc` is not a valid instruction.
+    InvalidOpcode { pc: u32, word: u32 },
+    /// A jump or memory access targeted an address outside memory.
+    OutOfBounds { pc: u32, address: i64 },
+}
+
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::EndOfProgram => write!(f, "end of program"),
+            HaltReason::StepLimit => write!(f, "step limit reached"),
+            HaltReason::InvalidOpcode { pc, word } => write!(f, "invalid instruction {:08X} at pc {}", word, pc),
+            HaltReason::OutOfBounds { pc, address } => write!(f, "address {} out of bounds at pc {}", address, pc),
+        }
+    }
+}
+
+/// Side effects of TELEPORT and BOMB, which only matter to the game around the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Teleport { pc: u32, imm1: u16, imm2: u8 },
+    Bomb { pc: u32, imm1: u16 },
+}
+
+/// State of the Sopt virtual machine.
+///
+/// There are six 32-bit registers with reg0 hard-wired to zero and a
+/// word-addressed memory holding the program image from address 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Machine {
+    pub registers: [u32; 6],
+    pub pc: u32,
+    pub memory: Vec<u32>,
+    /// Number of words loaded from the program; reaching it ends execution.
+    pub program_length: u32,
+    pub steps: u64,
+    pub events: Vec<Event>,
+}
+
+/// Final machine state together with the reason execution stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Execution {
+    pub machine: Machine,
+    pub halt: HaltReason,
+}
+
+/// Runs `program` on a fresh machine for at most `max_steps` instructions.
+pub fn run(program: &Program, max_steps: u64) -> Execution {
+    let mut machine = Machine::new(&program.encode(), DEFAULT_MEMORY_SIZE);
+    let halt = machine.run(max_steps);
+    Execution { machine, halt }
+}
+
+impl Machine {
+    /// Loads `words` at address 0 of a memory with at least `memory_size` words.
+    pub fn new(words: &[[u8; 4]], memory_size: usize) -> Self {
+        let mut memory: Vec<u32> = words.iter().map(|word| u32::from_be_bytes(*word)).collect();
+        memory.resize(memory_size.max(words.len()), 0);
+
+        Self { registers: [0; 6], pc: 0, memory, program_length: words.len() as u32, steps: 0, events: Vec::new() }
+    }
+
+    /// Executes instructions until the machine halts or `max_steps` more steps were taken.
+    pub fn run(&mut self, max_steps: u64) -> HaltReason {
+        for _ in 0..max_steps {
+            if let Some(halt) = self.step() {
+                return halt;
+            }
+        }
+        HaltReason::StepLimit
+    }
+
+    /// Executes a single instruction, returning why the machine halted if it did.
+    pub fn step(&mut self) -> Option<HaltReason> {
+        self.execute().err()
+    }
+
+    fn execute(&mut self) -> Result<(), HaltReason> {
+        if self.pc >= self.program_length {
+            return Err(HaltReason::EndOfProgram);
+        }
+
+        let pc = self.pc;
+        let word = self.memory[pc as usize];
+        let Some(instruction) = Instruction::decode(word.to_be_bytes()) else {
+            return Err(HaltReason::InvalidOpcode { pc, word });
+        };
+
+        let mut next = i64::from(pc) + 1;
+        match instruction {
+            Instruction::Nop => {}
+            Instruction::Add(reg) => {
+                let value = self.operand(reg.reg2, reg.imm1);
+                self.write(reg.reg1, self.registers[reg.reg1 as usize].wrapping_add(value));
+            }
+            Instruction::Sub(reg) => {
+                let value = self.operand(reg.reg2, reg.imm1);
+                self.write(reg.reg1, self.registers[reg.reg1 as usize].wrapping_sub(value));
+            }
+            Instruction::Mul(reg) => {
+                let value = self.operand(reg.reg2, reg.imm1);
+                self.write(reg.reg1, self.registers[reg.reg1 as usize].wrapping_mul(value));
+            }
+            Instruction::Mov(reg) => self.write(reg.reg1, self.operand(reg.reg2, reg.imm1)),
+            Instruction::Load(mem) => {
+                let address = self.address(mem.reg2, mem.imm1)?;
+                self.write(mem.reg1, self.memory[address]);
+            }
+            Instruction::Store(mem) => {
+                let address = self.address(mem.reg2, mem.imm1)?;
+                self.memory[address] = self.registers[mem.reg1 as usize];
+            }
+            Instruction::Jump(jump) | Instruction::RevJump(jump) | Instruction::LtJump(jump)
+            | Instruction::RevLtJump(jump) | Instruction::NeqJump(jump) | Instruction::RevNeqJump(jump) => {
+                let (left, right) = (self.registers[jump.reg1 as usize], self.registers[jump.reg2 as usize]);
+                let taken = match instruction {
+                    Instruction::Jump(_) | Instruction::RevJump(_) => left == right,
+                    Instruction::LtJump(_) | Instruction::RevLtJump(_) => left < right,
+                    _ => left != right,
+                };
+                if taken {
+                    next = match instruction {
+                        Instruction::RevJump(_) | Instruction::RevLtJump(_) | Instruction::RevNeqJump(_) => i64::from(pc) - i64::from(jump.imm1),
+                        _ => i64::from(pc) + i64::from(jump.imm1),
+                    };
+                }
+            }
+            Instruction::SetImmLow(set_imm) => {
+                let value = (self.registers[set_imm.reg1 as usize] & 0xFFFF_0000) | u32::from(set_imm.imm1);
+                self.write(set_imm.reg1, value);
+            }
+            Instruction::SetImmHigh(set_imm) => {
+                let value = (self.registers[set_imm.reg1 as usize] & 0x0000_FFFF) | (u32::from(set_imm.imm1) << 16);
+                self.write(set_imm.reg1, value);
+            }
+            Instruction::Teleport(teleport) => self.events.push(Event::Teleport { pc, imm1: teleport.imm1, imm2: teleport.imm2 }),
+            Instruction::Bomb(bomb) => self.events.push(Event::Bomb { pc, imm1: bomb.imm1 }),
+        }
+
+        if !(0..self.memory.len() as i64).contains(&next) {
+            return Err(HaltReason::OutOfBounds { pc, address: next });
+        }
+        self.pc = next as u32;
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// `reg + imm`, the second operand of ADD, SUB, MUL and MOV.
+    fn operand(&self, reg: u8, imm: u16) -> u32 {
+        self.registers[reg as usize].wrapping_add(u32::from(imm))
+    }
+
+    /// Memory index `reg + imm`, checked against the memory size.
+    fn address(&self, reg: u8, imm: u16) -> Result<usize, HaltReason> {
+        let address = i64::from(self.registers[reg as usize]) + i64::from(imm);
+        if address < self.memory.len() as i64 {
+            Ok(address as usize)
+        } else {
+            Err(HaltReason::OutOfBounds { pc: self.pc, address })
+        }
+    }
+
+    fn write(&mut self, reg: u8, value: u32) {
+        if reg != 0 {
+            self.registers[reg as usize] = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    fn execute(source: &str, max_steps: u64) -> Execution {
+        run(&assemble(source).expect(source), max_steps)
+    }
+
+    /// Final registers of a program that runs to its end.
+    fn registers(source: &str) -> [u32; 6] {
+        let execution = execute(source, 1000);
+        assert_eq!(execution.halt, HaltReason::EndOfProgram, "{}", source);
+        execution.machine.registers
+    }
+
+    #[test]
+    fn arithmetic_wraps_around() {
+        let registers = registers(
+            "reg1 -= reg0 + 1
+             reg2 = reg1 + 2
+             reg3 = 0x80000000
+             reg3 *= reg0 + 2
+             reg4 += reg1 + 0",
+        );
+        assert_eq!(registers, [0, 0xFFFF_FFFF, 1, 0, 0xFFFF_FFFF, 0]);
+    }
+
+    #[test]
+    fn forward_jumps() {
+        // reg3 stays 0 when the jump over `reg3 = 1` is taken.
+        let cases = [
+            ("if (reg1 == reg2) pc += 2", 5, 5, 0),
+            ("if (reg1 == reg2) pc += 2", 5, 6, 1),
+            ("if (reg1 < reg2) pc += 2", 5, 6, 0),
+            ("if (reg1 < reg2) pc += 2", 6, 5, 1),
+            ("if (reg1 < reg2) pc += 2", 5, 5, 1),
+            ("if (reg1 != reg2) pc += 2", 5, 6, 0),
+            ("if (reg1 != reg2) pc += 2", 5, 5, 1),
+        ];
+        for (jump, reg1, reg2, reg3) in cases {
+            let source = format!("reg1 = {}\nreg2 = {}\n{}\nreg3 = 1\nNOP", reg1, reg2, jump);
+            assert_eq!(registers(&source)[3], reg3, "{}", source);
+        }
+    }
+
+    #[test]
+    fn reverse_jumps() {
+        // Counts reg3 up for as long as the jump back is taken.
+        let cases = [
+            ("if (reg3 == reg2) pc -= 1", 1, 2),
+            ("if (reg3 < reg2) pc -= 1", 3, 3),
+            ("if (reg3 != reg2) pc -= 1", 3, 3),
+        ];
+        for (jump, reg2, reg3) in cases {
+            let source = format!("reg2 = {}\nreg3 += reg0 + 1\n{}", reg2, jump);
+            assert_eq!(registers(&source)[3], reg3, "{}", source);
+        }
+    }
+
+    #[test]
+    fn comparisons_are_unsigned() {
+        assert_eq!(registers("reg1 = -1\nif (reg0 < reg1) pc += 2\nreg3 = 1")[3], 0);
+    }
+
+    #[test]
+    fn load_and_store_with_offsets() {
+        let execution = execute(
+            "reg1 = 0x1234
+             reg2 = 100
+             mem[reg2 + 5] = reg1
+             reg3 = mem[105]
+             reg4 = mem[table + 1]
+             if (reg0 == reg0) goto end
+             table: .word 7, 8
+             end:",
+            1000,
+        );
+        assert_eq!(execution.halt, HaltReason::EndOfProgram);
+        assert_eq!(execution.machine.memory[105], 0x1234);
+        assert_eq!(execution.machine.registers[3], 0x1234);
+        assert_eq!(execution.machine.registers[4], 8);
+    }
+
+    #[test]
+    fn memory_access_out_of_bounds() {
+        let execution = execute("reg1 = 0xFFFF\nreg2 = mem[reg1 + 1]", 1000);
+        assert_eq!(execution.halt, HaltReason::OutOfBounds { pc: 1, address: 0x10000 });
+    }
+
+    #[test]
+    fn set_immediate_halves() {
+        let registers = registers(
+            "reg1[low] = 0x5678
+             reg1[high] = 0x1234
+             reg2 = reg1 + 0
+             reg2[low] = 1
+             reg3 = 0xDEADBEEF",
+        );
+        assert_eq!(&registers[1..4], &[0x1234_5678, 0x1234_0001, 0xDEAD_BEEF]);
+    }
+
+    #[test]
+    fn teleport_and_bomb_events() {
+        let execution = execute("NOP\nTELEPORT 17, 3\nBOMB 9", 1000);
+        assert_eq!(execution.halt, HaltReason::EndOfProgram);
+        assert_eq!(
+            execution.machine.events,
+            [Event::Teleport { pc: 1, imm1: 17, imm2: 3 }, Event::Bomb { pc: 2, imm1: 9 }]
+        );
+    }
+
+    #[test]
+    fn step_limit() {
+        let execution = execute("reg1 += reg0 + 1\nloop: if (reg0 == reg0) goto loop", 10);
+        assert_eq!(execution.halt, HaltReason::StepLimit);
+        assert_eq!((execution.machine.steps, execution.machine.pc), (10, 1));
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let execution = execute("NOP\n.word 0xFFFFFFFF", 1000);
+        assert_eq!(execution.halt, HaltReason::InvalidOpcode { pc: 1, word: 0xFFFF_FFFF });
+    }
+}
@@ -0,0 +1,218 @@
+use crate::instructions::{ParseError, SpannedError};
+use crate::lexer::{number_value, Span};
+use crate::symbols::SymbolTable;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+    /// `!value`, 1 for zero and 0 for anything else.
+    LogicalNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    /// Comparisons give 1 if they hold and 0 if not.
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// `&&` and `||` give 1 or 0 and skip the right side when the left one decides the result.
+    LogicalAnd,
+    LogicalOr,
+}
+
+/// Immediate expression, folded to a number at assembly time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    /// Literal exactly as written, see [`number_value`].
+    Number(String, Span),
+    /// Name of a constant or label.
+    Symbol(String, Span),
+    Unary(UnaryOperator, Box<Expression>, Span),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>, Span),
+}
+
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Number(_, span) | Expression::Symbol(_, span)
+            | Expression::Unary(_, _, span) | Expression::Binary(_, _, _, span) => *span,
+        }
+    }
+
+    /// The same expression covering `span`, used to include surrounding parentheses.
+    pub fn with_span(self, span: Span) -> Expression {
+        match self {
+            Expression::Number(literal, _) => Expression::Number(literal, span),
+            Expression::Symbol(name, _) => Expression::Symbol(name, span),
+            Expression::Unary(operator, operand, _) => Expression::Unary(operator, operand, span),
+            Expression::Binary(operator, left, right, _) => Expression::Binary(operator, left, right, span),
+        }
+    }
+
+    /// Folds the expression using the values in `symbols`.
+    ///
+    /// Returns `None` if a literal in it is not a valid number.
+    pub fn evaluate(&self, symbols: &SymbolTable) -> Result<Option<i64>, SpannedError> {
+        match self {
+            Expression::Number(literal, _) => Ok(number_value(literal)),
+            Expression::Symbol(name, span) => match symbols.get(name) {
+                Some(symbol) => Ok(Some(symbol.value)),
+                None => Err(symbols.undefined(name, *span)),
+            },
+            Expression::Unary(operator, operand, span) => {
+                let Some(value) = operand.evaluate(symbols)? else { return Ok(None) };
+                match operator {
+                    UnaryOperator::Negate => value.checked_neg().ok_or_else(|| ParseError::ExpressionOverflow.at(*span)).map(Some),
+                    UnaryOperator::Not => Ok(Some(!value)),
+                    UnaryOperator::LogicalNot => Ok(Some(i64::from(value == 0))),
+                }
+            }
+            Expression::Binary(operator @ (BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr), left, right, _) => {
+                let Some(left) = left.evaluate(symbols)? else { return Ok(None) };
+                if (left != 0) == (*operator == BinaryOperator::LogicalOr) {
+                    return Ok(Some(i64::from(left != 0)));
+                }
+                Ok(right.evaluate(symbols)?.map(|right| i64::from(right != 0)))
+            }
+            Expression::Binary(operator, left, right, span) => {
+                let (Some(left), Some(right)) = (left.evaluate(symbols)?, right.evaluate(symbols)?) else { return Ok(None) };
+                let value = match operator {
+                    BinaryOperator::Add => left.checked_add(right),
+                    BinaryOperator::Subtract => left.checked_sub(right),
+                    BinaryOperator::Multiply => left.checked_mul(right),
+                    BinaryOperator::Divide | BinaryOperator::Remainder if right == 0 => {
+                        return Err(ParseError::DivisionByZero.at(*span));
+                    }
+                    BinaryOperator::Divide => left.checked_div(right),
+                    BinaryOperator::Remainder => left.checked_rem(right),
+                    BinaryOperator::ShiftLeft => u32::try_from(right).ok().and_then(|shift| left.checked_shl(shift)),
+                    BinaryOperator::ShiftRight => u32::try_from(right).ok().and_then(|shift| left.checked_shr(shift)),
+                    BinaryOperator::And => Some(left & right),
+                    BinaryOperator::Or => Some(left | right),
+                    BinaryOperator::Xor => Some(left ^ right),
+                    BinaryOperator::Equal => Some(i64::from(left == right)),
+                    BinaryOperator::NotEqual => Some(i64::from(left != right)),
+                    BinaryOperator::Less => Some(i64::from(left < right)),
+                    BinaryOperator::LessEqual => Some(i64::from(left <= right)),
+                    BinaryOperator::Greater => Some(i64::from(left > right)),
+                    BinaryOperator::GreaterEqual => Some(i64::from(left >= right)),
+                    BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr => unreachable!("logical operators are evaluated above"),
+                };
+                value.ok_or_else(|| ParseError::ExpressionOverflow.at(*span)).map(Some)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_line, OperandKind, StatementKind};
+    use crate::symbols::SymbolKind;
+
+    /// Value of `text` as the value of a constant, with `SIZE` = 8 and `BASE` = 0x100 defined.
+    fn evaluate(text: &str) -> Result<Option<i64>, SpannedError> {
+        let statement = parse_line(&format!("const X = {}", text), 1, 0).expect(text);
+        let StatementKind::Constant(constant) = statement.kind else { panic!("`{}` is not a constant", text) };
+        let OperandKind::Expression(expression) = constant.value.kind else { panic!("`{}` is not an expression", text) };
+
+        let mut symbols = SymbolTable::new();
+        let span = Span { start: 0, end: 0, line: 0, column: 0 };
+        symbols.define("SIZE", SymbolKind::Constant, 8, span).unwrap();
+        symbols.define("BASE", SymbolKind::Label, 0x100, span).unwrap();
+        expression.evaluate(&symbols)
+    }
+
+    fn value(text: &str) -> i64 {
+        evaluate(text).expect(text).expect(text)
+    }
+
+    /// Code of the error `text` fails with, and the part of it the error points at.
+    fn error(text: &str) -> (&'static str, String) {
+        let err = evaluate(text).expect_err(text);
+        let start = err.span.start - "const X = ".len();
+        (err.error.code(), text[start..start + err.span.end - err.span.start].to_owned())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(value("1 + 2 * 3"), 7);
+        assert_eq!(value("(1 + 2) * 3"), 9);
+        assert_eq!(value("1 << 2 + 1"), 8);
+        assert_eq!(value("6 & 3 | 8"), 10);
+        assert_eq!(value("1 | 6 ^ 3 & 2"), 5);
+        assert_eq!(value("1 + 1 == 2"), 1);
+        assert_eq!(value("1 < 2 == 1"), 1);
+        assert_eq!(value("0 || 2 && 3"), 1);
+        assert_eq!(value("BASE + 4*SIZE"), 0x120);
+    }
+
+    #[test]
+    fn left_associativity() {
+        assert_eq!(value("10 - 3 - 2"), 5);
+        assert_eq!(value("100 / 10 / 5"), 2);
+        assert_eq!(value("17 % 10 % 4"), 3);
+        assert_eq!(value("64 >> 2 >> 1"), 8);
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(value("-5"), -5);
+        assert_eq!(value("--5"), 5);
+        assert_eq!(value("-SIZE * 2"), -16);
+        assert_eq!(value("~0"), -1);
+        assert_eq!(value("~0xFF & 0xFFF"), 0xF00);
+        assert_eq!(value("!0"), 1);
+        assert_eq!(value("!SIZE"), 0);
+    }
+
+    #[test]
+    fn division_truncates_towards_zero() {
+        assert_eq!(value("-7 / 2"), -3);
+        assert_eq!(value("-7 % 2"), -1);
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(error("0x7FFFFFFFFFFFFFFF + 1"), ("E0019", "0x7FFFFFFFFFFFFFFF + 1".to_owned()));
+        assert_eq!(error("1 + 0x7FFFFFFFFFFFFFFF * 2"), ("E0019", "0x7FFFFFFFFFFFFFFF * 2".to_owned()));
+        assert_eq!(error("-0x7FFFFFFFFFFFFFFF - 2"), ("E0019", "-0x7FFFFFFFFFFFFFFF - 2".to_owned()));
+        assert_eq!(error("1 << 64"), ("E0019", "1 << 64".to_owned()));
+        assert_eq!(error("1 >> -1"), ("E0019", "1 >> -1".to_owned()));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(error("SIZE / 0"), ("E0018", "SIZE / 0".to_owned()));
+        assert_eq!(error("1 + SIZE % (SIZE - 8)"), ("E0018", "SIZE % (SIZE - 8)".to_owned()));
+        // The right side of `&&` and `||` is skipped when the left one decides.
+        assert_eq!(value("0 && 1 / 0"), 0);
+        assert_eq!(value("1 || 1 / 0"), 1);
+    }
+
+    #[test]
+    fn unknown_symbols() {
+        assert_eq!(error("SIZE + COUNT"), ("E0016", "COUNT".to_owned()));
+        let err = evaluate("SIZ * 2").unwrap_err();
+        assert_eq!(err.help.as_deref(), Some("did you mean `SIZE`?"));
+    }
+
+    #[test]
+    fn invalid_literal() {
+        assert_eq!(evaluate("0x + 1").unwrap(), None);
+    }
+}
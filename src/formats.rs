@@ -0,0 +1,159 @@
+use crate::instructions::helpers::format_word;
+use crate::program::Program;
+
+/// The ways an assembled program can be written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Hex words annotated with the source they came from, see [`Program`]'s `Display`.
+    #[default]
+    Tik,
+    /// One hex word per line and nothing else.
+    CompactTik,
+    /// The words back to back, most significant byte first.
+    Binary,
+    /// Intel HEX records of the binary, addressed in bytes from 0.
+    IntelHex,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Tik, Format::CompactTik, Format::Binary, Format::IntelHex];
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Self::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Tik => "tik",
+            Format::CompactTik => "compact",
+            Format::Binary => "bin",
+            Format::IntelHex => "hex",
+        }
+    }
+
+    /// Extension of files written in this format, including the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Tik | Format::CompactTik => ".tik",
+            Format::Binary => ".bin",
+            Format::IntelHex => ".hex",
+        }
+    }
+
+    /// Writes `program` in this format.
+    pub fn write(&self, program: &Program) -> Vec<u8> {
+        match self {
+            Format::Tik => program.to_string().into_bytes(),
+            Format::CompactTik => program.encode().iter().map(|word| format_word(word) + "\n").collect::<String>().into_bytes(),
+            Format::Binary => program.encode().concat(),
+            Format::IntelHex => intel_hex(&program.encode().concat()).into_bytes(),
+        }
+    }
+}
+
+/// Bytes per Intel HEX data record.
+const RECORD_LENGTH: usize = 16;
+
+/// Formats `bytes` as Intel HEX data records followed by an end-of-file record.
+///
+/// Data beyond the first 64 KiB is preceded by an extended linear address record for its upper 16 address bits.
+fn intel_hex(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    let mut upper = 0;
+
+    for (index, chunk) in bytes.chunks(RECORD_LENGTH).enumerate() {
+        let address = index * RECORD_LENGTH;
+        if address >> 16 != upper {
+            upper = address >> 16;
+            output.push_str(&record(0x04, 0, &(upper as u16).to_be_bytes()));
+        }
+        output.push_str(&record(0x00, address as u16, chunk));
+    }
+
+    output.push_str(&record(0x01, 0, &[]));
+    output
+}
+
+/// A single `:LLAAAATT<data>CC` record, where the checksum makes all its bytes sum to zero.
+fn record(kind: u8, address: u16, data: &[u8]) -> String {
+    let [high, low] = address.to_be_bytes();
+    let mut bytes = vec![data.len() as u8, high, low, kind];
+    bytes.extend_from_slice(data);
+    let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+    bytes.push(checksum);
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    format!(":{}\n", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble;
+
+    const SOURCE: &str = "reg1 += reg3 + 99
+                          if (reg1 != reg2) pc -= 1
+                          TELEPORT 17, 3
+                          .word 0xDEADBEEF, -1";
+
+    fn program(source: &str) -> Program {
+        assemble(source).expect(source)
+    }
+
+    fn intel_hex_of(source: &str) -> String {
+        String::from_utf8(Format::IntelHex.write(&program(source))).unwrap()
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for format in Format::ALL {
+            assert_eq!(Format::from_name(format.name()), Some(format));
+        }
+        assert_eq!(Format::from_name("elf"), None);
+    }
+
+    #[test]
+    fn intel_hex_record() {
+        assert_eq!(intel_hex_of(".word 0x01020304"), ":0400000001020304F2\n:00000001FF\n");
+    }
+
+    #[test]
+    fn intel_hex_splits_records() {
+        let zeros = |count| "00".repeat(count);
+        let expected = format!(":10000000{}F0\n:04001000{}EC\n:00000001FF\n", zeros(16), zeros(4));
+        assert_eq!(intel_hex_of(".zero 5"), expected);
+    }
+
+    #[test]
+    fn intel_hex_extended_address() {
+        let hex = intel_hex_of(".zero 16385");
+        let records: Vec<&str> = hex.lines().collect();
+        assert_eq!(records.len(), 4096 + 1 + 1 + 1);
+        assert_eq!(records[4096], ":020000040001F9");
+        assert_eq!(records[4097], ":0400000000000000FC");
+        assert_eq!(records[4098], ":00000001FF");
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        let program = program(SOURCE);
+        let text = String::from_utf8(Format::CompactTik.write(&program)).unwrap();
+        let words: Vec<[u8; 4]> = text
+            .lines()
+            .map(|line| {
+                let bytes: Vec<u8> = line.split(' ').map(|byte| u8::from_str_radix(byte, 16).unwrap()).collect();
+                bytes.try_into().unwrap()
+            })
+            .collect();
+        assert_eq!(words, program.encode());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let program = program(SOURCE);
+        let bytes = Format::Binary.write(&program);
+        let words: Vec<[u8; 4]> = bytes.chunks(4).map(|word| word.try_into().unwrap()).collect();
+        assert_eq!(words, program.encode());
+        assert_eq!(&bytes[12..], &[0xDE, 0xAD, 0xBE, 0xEF, 0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::lexer::{tokenize, Token, TokenKind};
+
+/// Spaces added for every block a line is nested in.
+const INDENT: usize = 4;
+
+/// Formats Sopt source the same way everywhere.
+///
+/// Every line keeps its tokens and comment, but gets one space between operands and around binary
+/// operators, none inside brackets, and is indented by the `.macro`, `.rep`, `.for` and `.if` blocks
+/// around it. Trailing whitespace and blank lines at the end are removed. A line the formatter can not
+/// make sense of, like one with an unknown character, is only trimmed.
+pub fn format_source(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut depth: usize = 0;
+
+    for text in source.lines() {
+        let tokens = tokenize(text, 1, 0);
+        let (code, comment) = match tokens.last() {
+            Some(Token { kind: TokenKind::Comment(_), .. }) => (&tokens[..tokens.len() - 1], tokens.last()),
+            _ => (&tokens[..], None),
+        };
+
+        if code.iter().any(|token| matches!(token.kind, TokenKind::Unknown(_))) {
+            output.push_str(text.trim_end());
+            output.push('\n');
+            continue;
+        }
+
+        let level = match block_directive(code) {
+            Some(".macro" | ".rep" | ".for" | ".if" | ".ifdef" | ".ifndef") => {
+                depth += 1;
+                depth - 1
+            }
+            Some(".elif" | ".else") => depth.saturating_sub(1),
+            Some(".endm" | ".endr" | ".endif") => {
+                depth = depth.saturating_sub(1);
+                depth
+            }
+            _ => depth,
+        };
+
+        let mut line = spaced(code);
+        if tokenize(&line, 1, 0).iter().map(|token| &token.kind).ne(code.iter().map(|token| &token.kind)) {
+            // Spacing would change what the line means, so it stays as it was written.
+            line = text[code.first().map_or(0, |token| token.span.start)..code.last().map_or(0, |token| token.span.end)].to_owned();
+        }
+        if let Some(comment) = comment {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(comment.text.trim_end());
+        }
+        if !line.is_empty() {
+            output.push_str(&" ".repeat(level * INDENT));
+            output.push_str(&line);
+        }
+        output.push('\n');
+    }
+
+    let end = output.trim_end().len();
+    output.truncate(end);
+    if !output.is_empty() {
+        output.push('\n');
+    }
+    output
+}
+
+/// The directive that opens, continues or closes a block, after an optional label.
+fn block_directive(code: &[Token]) -> Option<&str> {
+    let first = match (code.first().map(|token| &token.kind), code.get(1).map(|token| &token.kind)) {
+        (Some(TokenKind::Identifier(_)), Some(TokenKind::Colon)) => code.get(2),
+        _ => code.first(),
+    }?;
+    match &first.kind {
+        TokenKind::Directive(name) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// The tokens of one line joined with the usual spacing.
+fn spaced(tokens: &[Token]) -> String {
+    let mut line = String::new();
+    // Whether the next token starts an operand, where `-` is a sign instead of a subtraction.
+    let mut operand_expected = true;
+    let mut previous: Option<&TokenKind> = None;
+    let mut after_unary = false;
+    let mut statement_start = 0;
+
+    for (index, token) in tokens.iter().enumerate() {
+        let unary = operand_expected && matches!(token.kind, TokenKind::Minus | TokenKind::Tilde | TokenKind::Bang);
+        let space = match (previous, &token.kind) {
+            (None, _) => false,
+            (_, TokenKind::Comma | TokenKind::RightParen | TokenKind::RightBracket | TokenKind::LeftBracket | TokenKind::Colon) => false,
+            (Some(TokenKind::LeftParen | TokenKind::LeftBracket | TokenKind::At), _) => false,
+            _ => !after_unary,
+        };
+        if space {
+            line.push(' ');
+        }
+        line.push_str(&token.text);
+
+        if token.kind == TokenKind::Colon {
+            statement_start = index + 1;
+        }
+        operand_expected = match &token.kind {
+            // The mnemonic, macro name or keyword a statement starts with is followed by an operand.
+            TokenKind::Identifier(_) => index == statement_start,
+            TokenKind::Number(_) | TokenKind::Register(_) | TokenKind::String(_) | TokenKind::RightParen | TokenKind::RightBracket => false,
+            _ => true,
+        };
+        after_unary = unary;
+        previous = Some(&token.kind);
+    }
+    line
+}
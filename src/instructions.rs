@@ -1,7 +1,17 @@
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
-use colored::Colorize;
-use regex::Regex;
-use crate::instructions::helpers::{make_instruction_number, replace_first, replace_last};
+use crate::instructions::bomb::Bomb;
+use crate::instructions::helpers::format_word;
+use crate::instructions::jumps::Jump;
+use crate::instructions::mem_manipulation::MemManipulation;
+use crate::instructions::reg_manipulation::RegManipulation;
+use crate::instructions::set_imms::SetImm;
+use crate::instructions::teleport::Teleport;
+use crate::lexer::Span;
+use crate::lints::Warning;
+use crate::parser::Operand;
+use crate::preprocessor::substitute;
+use crate::symbols::Context;
 
 pub mod jumps;
 pub mod reg_manipulation;
@@ -11,9 +21,10 @@ pub mod teleport;
 pub mod bomb;
 pub mod helpers;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParseError {
-    RegexDoesNotMatch,
+    UnknownInstruction,
+    UnexpectedToken(String, &'static str),
     CannotWriteIntoReg0,
     MissingReg1,
     MissingReg2,
@@ -21,19 +32,448 @@ pub enum ParseError {
     MissingImm2,
     UnsupportedReg1(String, u8, u8),
     UnsupportedReg2(String, u8, u8),
-    UnsupportedImm1(String, u32),
-    UnsupportedImm2(String, u32)
+    UnsupportedImm1(String, i64, i64),
+    UnsupportedImm2(String, i64, i64),
+    UnknownDirective(String),
+    MissingConstantValue(String),
+    InvalidConstantValue(String),
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    JumpTooFar(String, i64),
+    DivisionByZero,
+    ExpressionOverflow,
+    DuplicateMacro(String),
+    ReservedMacroName(String),
+    DuplicateParameter(String),
+    NestedMacro,
+    UnterminatedMacro(String),
+    UnmatchedEndm,
+    MacroArgumentCount(String, usize, usize),
+    MacroTooDeep(String, usize),
+    IncludeNotFound(String),
+    IncludeCycle(String),
+    IncludeFailed(String, String),
+    OrgOverlap(String, i64, u32),
+    InvalidCondition(String),
+    ConditionalLabel(String),
+    UnmatchedConditional(String),
+    ConditionalAfterElse(String),
+    UnterminatedConditional(String),
+    UnmatchedEndr,
+    UnterminatedRepeat(String),
+    RepeatTooLarge(String, usize),
+    ProgramTooLarge(u32),
+    /// Not an error but a warning from the [lints](crate::lints), unless it was denied.
+    Lint(Warning),
 }
 
-pub trait Instruction {
-    type Instruction;
-    fn parse(instruction: &str, regex: Regex, instruction_number: u8) -> Result<Self::Instruction, ParseError>;
+/// A [`ParseError`] with the span it points at and the instruction it was found in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpannedError {
+    pub error: ParseError,
+    pub span: Span,
+    pub mnemonic: Option<Mnemonic>,
+    /// Advice for this particular mistake, like a name that was probably meant.
+    pub help: Option<String>,
+}
+
+impl ParseError {
+    pub fn at(self, span: Span) -> SpannedError {
+        SpannedError { error: self, span, mnemonic: None, help: None }
+    }
+
+    /// Stable code of the error, like `E0003`, for looking it up and for tools.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::UnknownInstruction => "E0001",
+            ParseError::UnexpectedToken(..) => "E0002",
+            ParseError::CannotWriteIntoReg0 => "E0003",
+            ParseError::MissingReg1 => "E0004",
+            ParseError::MissingReg2 => "E0005",
+            ParseError::MissingImm1 => "E0006",
+            ParseError::MissingImm2 => "E0007",
+            ParseError::UnsupportedReg1(..) => "E0008",
+            ParseError::UnsupportedReg2(..) => "E0009",
+            ParseError::UnsupportedImm1(..) => "E0010",
+            ParseError::UnsupportedImm2(..) => "E0011",
+            ParseError::UnknownDirective(..) => "E0012",
+            ParseError::MissingConstantValue(..) => "E0013",
+            ParseError::InvalidConstantValue(..) => "E0014",
+            ParseError::DuplicateSymbol(..) => "E0015",
+            ParseError::UndefinedSymbol(..) => "E0016",
+            ParseError::JumpTooFar(..) => "E0017",
+            ParseError::DivisionByZero => "E0018",
+            ParseError::ExpressionOverflow => "E0019",
+            ParseError::DuplicateMacro(..) => "E0020",
+            ParseError::ReservedMacroName(..) => "E0021",
+            ParseError::DuplicateParameter(..) => "E0022",
+            ParseError::NestedMacro => "E0023",
+            ParseError::UnterminatedMacro(..) => "E0024",
+            ParseError::UnmatchedEndm => "E0025",
+            ParseError::MacroArgumentCount(..) => "E0026",
+            ParseError::MacroTooDeep(..) => "E0027",
+            ParseError::IncludeNotFound(..) => "E0028",
+            ParseError::IncludeCycle(..) => "E0029",
+            ParseError::IncludeFailed(..) => "E0030",
+            ParseError::OrgOverlap(..) => "E0031",
+            ParseError::InvalidCondition(..) => "E0032",
+            ParseError::ConditionalLabel(..) => "E0033",
+            ParseError::UnmatchedConditional(..) => "E0034",
+            ParseError::ConditionalAfterElse(..) => "E0035",
+            ParseError::UnterminatedConditional(..) => "E0036",
+            ParseError::UnmatchedEndr => "E0037",
+            ParseError::UnterminatedRepeat(..) => "E0038",
+            ParseError::RepeatTooLarge(..) => "E0039",
+            ParseError::ProgramTooLarge(..) => "E0040",
+            ParseError::Lint(warning) => warning.lint().code(),
+        }
+    }
+
+    /// Advice on fixing the error, for the errors where the message alone may not be enough.
+    pub fn help(&self) -> Option<&'static str> {
+        match self {
+            ParseError::CannotWriteIntoReg0 => Some("reg0 always reads as 0, write into one of reg1-reg5 instead"),
+            ParseError::NestedMacro => Some("define the inner macro above the outer one, it can still be used inside it"),
+            ParseError::UnterminatedMacro(_) => Some("add `.endm` after the last line of the macro"),
+            ParseError::MacroTooDeep(_, _) => Some("a macro that invokes itself needs an `.if` around the invocation to stop"),
+            ParseError::IncludeNotFound(_) => Some("files are looked for next to the file including them and in every `--include-path` directory"),
+            ParseError::OrgOverlap(_, _, _) => Some("`.org` can only move forward, use a higher address or move the `.org` up"),
+            ParseError::ConditionalLabel(_) => Some("put the label on a line of its own"),
+            ParseError::UnterminatedConditional(_) => Some("add `.endif` after the last line of the block"),
+            ParseError::UnterminatedRepeat(_) => Some("add `.endr` after the last line of the block"),
+            ParseError::ProgramTooLarge(_) => Some("`.zero`, `.org` and `.align` count towards the size, as does every repetition of a `.rep` or `.for` block"),
+            _ => None,
+        }
+    }
+
+    /// The error with the names in the source text it quotes replaced according to `names`.
+    pub fn rename(self, names: &HashMap<String, String>) -> ParseError {
+        let rename = |text: String| substitute(&text, names);
+        match self {
+            ParseError::UnexpectedToken(text, expected) => ParseError::UnexpectedToken(rename(text), expected),
+            ParseError::UnsupportedReg1(text, min, max) => ParseError::UnsupportedReg1(rename(text), min, max),
+            ParseError::UnsupportedReg2(text, min, max) => ParseError::UnsupportedReg2(rename(text), min, max),
+            ParseError::UnsupportedImm1(text, min, max) => ParseError::UnsupportedImm1(rename(text), min, max),
+            ParseError::UnsupportedImm2(text, min, max) => ParseError::UnsupportedImm2(rename(text), min, max),
+            ParseError::MissingConstantValue(name) => ParseError::MissingConstantValue(rename(name)),
+            ParseError::InvalidConstantValue(text) => ParseError::InvalidConstantValue(rename(text)),
+            ParseError::DuplicateSymbol(name) => ParseError::DuplicateSymbol(rename(name)),
+            ParseError::UndefinedSymbol(name) => ParseError::UndefinedSymbol(rename(name)),
+            ParseError::JumpTooFar(text, distance) => ParseError::JumpTooFar(rename(text), distance),
+            ParseError::OrgOverlap(text, target, address) => ParseError::OrgOverlap(rename(text), target, address),
+            ParseError::InvalidCondition(text) => ParseError::InvalidCondition(rename(text)),
+            ParseError::ConditionalLabel(name) => ParseError::ConditionalLabel(rename(name)),
+            ParseError::RepeatTooLarge(text, limit) => ParseError::RepeatTooLarge(rename(text), limit),
+            error => error,
+        }
+    }
+
+    /// The values an out of range register or immediate may take, as `(min, max)`.
+    pub fn expected(&self) -> Option<(i64, i64)> {
+        match self {
+            ParseError::UnsupportedReg1(_, min, max) | ParseError::UnsupportedReg2(_, min, max) => Some((i64::from(*min), i64::from(*max))),
+            ParseError::UnsupportedImm1(_, min, max) | ParseError::UnsupportedImm2(_, min, max) => Some((*min, *max)),
+            _ => None,
+        }
+    }
+}
+
+impl SpannedError {
+    pub fn during(self, mnemonic: Mnemonic) -> SpannedError {
+        SpannedError { mnemonic: Some(mnemonic), ..self }
+    }
+
+    pub fn with_help(self, help: Option<String>) -> SpannedError {
+        SpannedError { help, ..self }
+    }
+}
+
+/// Operand positions, named after the fields of the encoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    Reg1,
+    Reg2,
+    Imm1,
+    Imm2,
+}
+
+impl Slot {
+    pub fn missing(self) -> ParseError {
+        match self {
+            Slot::Reg1 => ParseError::MissingReg1,
+            Slot::Reg2 => ParseError::MissingReg2,
+            Slot::Imm1 => ParseError::MissingImm1,
+            Slot::Imm2 => ParseError::MissingImm2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mnemonic {
+    Nop,
+    Add,
+    Sub,
+    Mul,
+    Load,
+    Store,
+    Mov,
+    Jump,
+    RevJump,
+    LtJump,
+    RevLtJump,
+    NeqJump,
+    RevNeqJump,
+    SetImmLow,
+    SetImmHigh,
+    Teleport,
+    Bomb,
+}
+
+impl Mnemonic {
+    pub const ALL: [Mnemonic; 17] = [
+        Mnemonic::Nop, Mnemonic::Add, Mnemonic::Sub, Mnemonic::Mul, Mnemonic::Load, Mnemonic::Store, Mnemonic::Mov,
+        Mnemonic::Jump, Mnemonic::RevJump, Mnemonic::LtJump, Mnemonic::RevLtJump, Mnemonic::NeqJump, Mnemonic::RevNeqJump,
+        Mnemonic::SetImmLow, Mnemonic::SetImmHigh, Mnemonic::Teleport, Mnemonic::Bomb,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Mnemonic> {
+        Mnemonic::ALL.into_iter().find(|mnemonic| mnemonic.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mnemonic::Nop => "NOP",
+            Mnemonic::Add => "ADD",
+            Mnemonic::Sub => "SUB",
+            Mnemonic::Mul => "MUL",
+            Mnemonic::Load => "LOAD",
+            Mnemonic::Store => "STORE",
+            Mnemonic::Mov => "MOV",
+            Mnemonic::Jump => "JUMP",
+            Mnemonic::RevJump => "REVJUMP",
+            Mnemonic::LtJump => "LTJUMP",
+            Mnemonic::RevLtJump => "REVLTJUMP",
+            Mnemonic::NeqJump => "NEQJUMP",
+            Mnemonic::RevNeqJump => "REVNEQJUMP",
+            Mnemonic::SetImmLow => "SETIMMLOW",
+            Mnemonic::SetImmHigh => "SETIMMHIGH",
+            Mnemonic::Teleport => "TELEPORT",
+            Mnemonic::Bomb => "BOMB",
+        }
+    }
+
+    pub fn opcode(self) -> u8 {
+        match self {
+            Mnemonic::Nop => 0x69,
+            Mnemonic::Add => 0x01,
+            Mnemonic::Sub => 0x02,
+            Mnemonic::Mul => 0x03,
+            Mnemonic::Load => 0x05,
+            Mnemonic::Store => 0x06,
+            Mnemonic::Mov => 0x07,
+            Mnemonic::Jump => 0x10,
+            Mnemonic::RevJump => 0x11,
+            Mnemonic::LtJump => 0x12,
+            Mnemonic::RevLtJump => 0x13,
+            Mnemonic::NeqJump => 0x14,
+            Mnemonic::RevNeqJump => 0x15,
+            Mnemonic::SetImmLow => 0x20,
+            Mnemonic::SetImmHigh => 0x21,
+            Mnemonic::Teleport => 0x42,
+            Mnemonic::Bomb => 0x50,
+        }
+    }
+
+    pub fn is_jump(self) -> bool {
+        (0x10..=0x15).contains(&self.opcode())
+    }
+
+    /// The forward (`pc +=`) or reverse (`pc -=`) variant of a jump with the same condition.
+    pub fn with_direction(self, forward: bool) -> Mnemonic {
+        match (self, forward) {
+            (Mnemonic::Jump | Mnemonic::RevJump, true) => Mnemonic::Jump,
+            (Mnemonic::Jump | Mnemonic::RevJump, false) => Mnemonic::RevJump,
+            (Mnemonic::LtJump | Mnemonic::RevLtJump, true) => Mnemonic::LtJump,
+            (Mnemonic::LtJump | Mnemonic::RevLtJump, false) => Mnemonic::RevLtJump,
+            (Mnemonic::NeqJump | Mnemonic::RevNeqJump, true) => Mnemonic::NeqJump,
+            (Mnemonic::NeqJump | Mnemonic::RevNeqJump, false) => Mnemonic::RevNeqJump,
+            (mnemonic, _) => mnemonic,
+        }
+    }
+
+    /// Operands in the order the mnemonic syntax takes them.
+    pub fn slots(self) -> &'static [Slot] {
+        match self {
+            Mnemonic::Nop => &[],
+            Mnemonic::Store => &[Slot::Reg1, Slot::Imm1, Slot::Reg2],
+            Mnemonic::SetImmLow | Mnemonic::SetImmHigh => &[Slot::Reg1, Slot::Imm1],
+            Mnemonic::Teleport => &[Slot::Imm1, Slot::Imm2],
+            Mnemonic::Bomb => &[Slot::Imm1],
+            _ => &[Slot::Reg1, Slot::Reg2, Slot::Imm1],
+        }
+    }
+}
+
+pub trait Parse: Sized {
+    /// Builds the instruction from operands ordered as in [`Mnemonic::slots`].
+    fn parse(operands: &[Operand], context: &Context) -> Result<Self, SpannedError>;
+}
+
+/// One Sopt instruction, one variant per opcode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Add(RegManipulation),
+    Sub(RegManipulation),
+    Mul(RegManipulation),
+    Load(MemManipulation),
+    Store(MemManipulation),
+    Mov(RegManipulation),
+    Jump(Jump),
+    RevJump(Jump),
+    LtJump(Jump),
+    RevLtJump(Jump),
+    NeqJump(Jump),
+    RevNeqJump(Jump),
+    SetImmLow(SetImm),
+    SetImmHigh(SetImm),
+    Teleport(Teleport),
+    Bomb(Bomb),
+}
+
+impl Instruction {
+    /// Builds a typed instruction from a parsed mnemonic and its operands.
+    ///
+    /// Jumps to an `@label` get the forward or reverse opcode depending on where the label is.
+    pub fn lower(mnemonic: Mnemonic, operands: &[Operand], context: &Context) -> Result<Instruction, SpannedError> {
+        let mnemonic = if mnemonic.is_jump() {
+            Jump::direction(mnemonic, operands, context).map_err(|err| err.during(mnemonic))?
+        } else {
+            mnemonic
+        };
+
+        let instruction = match mnemonic {
+            Mnemonic::Nop => Ok(Instruction::Nop),
+            Mnemonic::Add => RegManipulation::parse(operands, context).map(Instruction::Add),
+            Mnemonic::Sub => RegManipulation::parse(operands, context).map(Instruction::Sub),
+            Mnemonic::Mul => RegManipulation::parse(operands, context).map(Instruction::Mul),
+            Mnemonic::Load => MemManipulation::parse(operands, true, context).map(Instruction::Load),
+            Mnemonic::Store => MemManipulation::parse(operands, false, context).map(Instruction::Store),
+            Mnemonic::Mov => RegManipulation::parse(operands, context).map(Instruction::Mov),
+            Mnemonic::Jump => Jump::parse(operands, context).map(Instruction::Jump),
+            Mnemonic::RevJump => Jump::parse(operands, context).map(Instruction::RevJump),
+            Mnemonic::LtJump => Jump::parse(operands, context).map(Instruction::LtJump),
+            Mnemonic::RevLtJump => Jump::parse(operands, context).map(Instruction::RevLtJump),
+            Mnemonic::NeqJump => Jump::parse(operands, context).map(Instruction::NeqJump),
+            Mnemonic::RevNeqJump => Jump::parse(operands, context).map(Instruction::RevNeqJump),
+            Mnemonic::SetImmLow => SetImm::parse(operands, context).map(Instruction::SetImmLow),
+            Mnemonic::SetImmHigh => SetImm::parse(operands, context).map(Instruction::SetImmHigh),
+            Mnemonic::Teleport => Teleport::parse(operands, context).map(Instruction::Teleport),
+            Mnemonic::Bomb => Bomb::parse(operands, context).map(Instruction::Bomb),
+        };
+        instruction.map_err(|err| err.during(mnemonic))
+    }
+
+    pub fn mnemonic(&self) -> Mnemonic {
+        match self {
+            Instruction::Nop => Mnemonic::Nop,
+            Instruction::Add(_) => Mnemonic::Add,
+            Instruction::Sub(_) => Mnemonic::Sub,
+            Instruction::Mul(_) => Mnemonic::Mul,
+            Instruction::Load(_) => Mnemonic::Load,
+            Instruction::Store(_) => Mnemonic::Store,
+            Instruction::Mov(_) => Mnemonic::Mov,
+            Instruction::Jump(_) => Mnemonic::Jump,
+            Instruction::RevJump(_) => Mnemonic::RevJump,
+            Instruction::LtJump(_) => Mnemonic::LtJump,
+            Instruction::RevLtJump(_) => Mnemonic::RevLtJump,
+            Instruction::NeqJump(_) => Mnemonic::NeqJump,
+            Instruction::RevNeqJump(_) => Mnemonic::RevNeqJump,
+            Instruction::SetImmLow(_) => Mnemonic::SetImmLow,
+            Instruction::SetImmHigh(_) => Mnemonic::SetImmHigh,
+            Instruction::Teleport(_) => Mnemonic::Teleport,
+            Instruction::Bomb(_) => Mnemonic::Bomb,
+        }
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.mnemonic().opcode()
+    }
+
+    /// Decodes a machine word back into the instruction the assembler would have produced it from.
+    ///
+    /// Returns `None` for unknown opcodes and for words no source line assembles to,
+    /// such as registers above reg5, writes into reg0 or non-zero unused bits.
+    pub fn decode(word: [u8; 4]) -> Option<Instruction> {
+        let [opcode, regs, high, low] = word;
+        let mnemonic = Mnemonic::ALL.into_iter().find(|mnemonic| mnemonic.opcode() == opcode)?;
+        let (reg1, reg2) = (regs >> 4, regs & 0x0F);
+        let imm1 = u16::from_be_bytes([high, low]);
+
+        let reg = RegManipulation { reg1, reg2, imm1 };
+        let mem = MemManipulation { reg1, reg2, imm1 };
+        let jump = Jump { reg1, reg2, imm1 };
+        let set_imm = SetImm { reg1, imm1 };
+        let instruction = match mnemonic {
+            Mnemonic::Nop => Instruction::Nop,
+            Mnemonic::Add => Instruction::Add(reg),
+            Mnemonic::Sub => Instruction::Sub(reg),
+            Mnemonic::Mul => Instruction::Mul(reg),
+            Mnemonic::Load => Instruction::Load(mem),
+            Mnemonic::Store => Instruction::Store(mem),
+            Mnemonic::Mov => Instruction::Mov(reg),
+            Mnemonic::Jump => Instruction::Jump(jump),
+            Mnemonic::RevJump => Instruction::RevJump(jump),
+            Mnemonic::LtJump => Instruction::LtJump(jump),
+            Mnemonic::RevLtJump => Instruction::RevLtJump(jump),
+            Mnemonic::NeqJump => Instruction::NeqJump(jump),
+            Mnemonic::RevNeqJump => Instruction::RevNeqJump(jump),
+            Mnemonic::SetImmLow => Instruction::SetImmLow(set_imm),
+            Mnemonic::SetImmHigh => Instruction::SetImmHigh(set_imm),
+            Mnemonic::Teleport => Instruction::Teleport(Teleport { imm1: u16::from_be_bytes([regs, high]), imm2: low }),
+            Mnemonic::Bomb => Instruction::Bomb(Bomb { imm1: u16::from_be_bytes([regs, high]) }),
+        };
+
+        let registers_valid = match instruction {
+            Instruction::Add(_) | Instruction::Sub(_) | Instruction::Mul(_) | Instruction::Mov(_) | Instruction::Load(_) => {
+                (1..=5).contains(&reg1) && reg2 <= 5
+            }
+            Instruction::SetImmLow(_) | Instruction::SetImmHigh(_) => (1..=5).contains(&reg1),
+            Instruction::Store(_) | Instruction::Jump(_) | Instruction::RevJump(_) | Instruction::LtJump(_)
+            | Instruction::RevLtJump(_) | Instruction::NeqJump(_) | Instruction::RevNeqJump(_) => reg1 <= 5 && reg2 <= 5,
+            Instruction::Nop | Instruction::Teleport(_) | Instruction::Bomb(_) => true,
+        };
+
+        (registers_valid && instruction.encode() == word).then_some(instruction)
+    }
+
+    /// Encodes the instruction into its 4-byte machine word.
+    pub fn encode(&self) -> [u8; 4] {
+        let opcode = self.opcode();
+        match self {
+            Instruction::Nop => [opcode, 0, 0, 0],
+            Instruction::Add(reg) | Instruction::Sub(reg) | Instruction::Mul(reg) | Instruction::Mov(reg) => reg.encode(opcode),
+            Instruction::Load(mem) | Instruction::Store(mem) => mem.encode(opcode),
+            Instruction::Jump(jump) | Instruction::RevJump(jump) | Instruction::LtJump(jump)
+            | Instruction::RevLtJump(jump) | Instruction::NeqJump(jump) | Instruction::RevNeqJump(jump) => jump.encode(opcode),
+            Instruction::SetImmLow(set_imm) | Instruction::SetImmHigh(set_imm) => set_imm.encode(opcode),
+            Instruction::Teleport(teleport) => teleport.encode(opcode),
+            Instruction::Bomb(bomb) => bomb.encode(opcode),
+        }
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_word(&self.encode()))
+    }
 }
 
 impl Display for ParseError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let string: String = match self {
-            ParseError::RegexDoesNotMatch => "regex does not match".to_owned(),
+            ParseError::UnknownInstruction => "unknown instruction".to_owned(),
+            ParseError::UnexpectedToken(found, expected) => format!("unexpected `{}` (expected {})", found, expected),
             ParseError::CannotWriteIntoReg0 => "can not write into reg0".to_owned(),
             ParseError::MissingReg1 => "missing reg1 argument".to_owned(),
             ParseError::MissingReg2 => "missing reg2 argument".to_owned(),
@@ -41,58 +481,47 @@ impl Display for ParseError {
             ParseError::MissingImm2 => "missing imm2 argument".to_owned(),
             ParseError::UnsupportedReg1(_, min, max) => format!("unsupported reg number (supported: {}-{})", min, max),
             ParseError::UnsupportedReg2(_, min, max) => format!("unsupported reg number (supported: {}-{})", min, max),
-            ParseError::UnsupportedImm1(_, max) => format!("unsupported imm number (supported: 0-{})", max),
-            ParseError::UnsupportedImm2(_, max) => format!("unsupported imm number (supported: 0-{})", max)
+            ParseError::UnsupportedImm1(_, min, max) | ParseError::UnsupportedImm2(_, min, max) if *min < 0 => {
+                format!("unsupported imm number (supported: {} to {})", min, max)
+            }
+            ParseError::UnsupportedImm1(_, min, max) => format!("unsupported imm number (supported: {}-{})", min, max),
+            ParseError::UnsupportedImm2(_, min, max) => format!("unsupported imm number (supported: {}-{})", min, max),
+            ParseError::UnknownDirective(directive) => format!("unknown directive `{}`", directive),
+            ParseError::MissingConstantValue(name) => format!("missing value of constant `{}`", name),
+            ParseError::InvalidConstantValue(value) => format!("`{}` is not a valid constant value", value),
+            ParseError::DuplicateSymbol(name) => format!("`{}` is already defined", name),
+            ParseError::UndefinedSymbol(name) => format!("`{}` is not defined", name),
+            ParseError::JumpTooFar(_, distance) => format!("jump target is {} instructions away (supported: 0-65535)", distance.unsigned_abs()),
+            ParseError::DivisionByZero => "division by zero".to_owned(),
+            ParseError::ExpressionOverflow => "expression result does not fit into 64 bits".to_owned(),
+            ParseError::DuplicateMacro(name) => format!("macro `{}` is already defined", name),
+            ParseError::ReservedMacroName(name) => format!("`{}` is reserved and can not be a macro name", name),
+            ParseError::DuplicateParameter(name) => format!("parameter `{}` is listed more than once", name),
+            ParseError::NestedMacro => "macros can not be defined inside other macros".to_owned(),
+            ParseError::UnterminatedMacro(name) => format!("macro `{}` has no `.endm`", name),
+            ParseError::UnmatchedEndm => "`.endm` without `.macro`".to_owned(),
+            ParseError::MacroArgumentCount(name, expected, found) => {
+                format!("macro `{}` takes {} argument{} but {} {} given", name, expected, if *expected == 1 { "" } else { "s" }, found, if *found == 1 { "was" } else { "were" })
+            }
+            ParseError::MacroTooDeep(name, depth) => format!("macro `{}` is nested more than {} levels deep", name, depth),
+            ParseError::IncludeNotFound(path) => format!("can not find `{}` to include", path),
+            ParseError::IncludeCycle(path) => format!("`{}` is already being included, including it again would never end", path),
+            ParseError::IncludeFailed(path, reason) => format!("can not read `{}`: {}", path, reason),
+            ParseError::OrgOverlap(_, target, address) => {
+                format!("can not move to address {} because the words up to address {} are already placed", target, address - 1)
+            }
+            ParseError::InvalidCondition(condition) => format!("`{}` is not a valid condition", condition),
+            ParseError::ConditionalLabel(name) => format!("label `{}` can not be defined on a conditional directive", name),
+            ParseError::UnmatchedConditional(directive) => format!("`{}` without `.if` in the same file or macro", directive),
+            ParseError::ConditionalAfterElse(directive) => format!("`{}` after `.else`", directive),
+            ParseError::UnterminatedConditional(directive) => format!("`{}` has no `.endif`", directive),
+            ParseError::UnmatchedEndr => "`.endr` without `.rep` or `.for` in the same file or macro".to_owned(),
+            ParseError::UnterminatedRepeat(directive) => format!("`{}` has no `.endr`", directive),
+            ParseError::RepeatTooLarge(_, limit) => format!("repeated blocks would add more than {} lines in total", limit),
+            ParseError::ProgramTooLarge(words) => format!("the program does not fit in the {} words addresses can reach", words),
+            ParseError::Lint(warning) => warning.to_string(),
         };
 
         write!(f, "{}", string)
     }
 }
-
-pub fn build_error(err: ParseError, problem_line: String, instruction: &u8) -> String {
-    let mut problem = String::from("in your program on line:\n\n");
-
-    // println!("{:?}", err);
-    problem.push_str(&match err {
-        ParseError::CannotWriteIntoReg0 => {
-            format!(
-                "{}\n\nproblem: {}",
-                replace_first(&problem_line, "reg0", &"reg0".red().to_string()),
-                err.to_string().red()
-            )
-        }
-        ParseError::RegexDoesNotMatch | ParseError::MissingReg1 | ParseError::MissingReg2 | ParseError::MissingImm1 | ParseError::MissingImm2 => {
-            format!("{}\n\nproblem: {}", problem_line, err.to_string().red())
-        }
-        ParseError::UnsupportedReg1(ref invalid_reg, _, _) => {
-            format!(
-                "{}\n\nproblem: {}",
-                replace_first(&problem_line, invalid_reg, &invalid_reg.red().to_string()),
-                err.to_string().red()
-            )
-        }
-        ParseError::UnsupportedReg2(ref invalid_reg, _, _) => {
-            format!(
-                "{}\n\nproblem: {}",
-                replace_last(&problem_line, invalid_reg, &invalid_reg.red().to_string()),
-                err.to_string().red()
-            )
-        }
-        ParseError::UnsupportedImm1(ref invalid_imm, _) => {
-            format!(
-                "{}\n\nproblem: {}",
-                replace_first(&problem_line, invalid_imm, &invalid_imm.red().to_string()),
-                err.to_string().red()
-            )
-        }
-        ParseError::UnsupportedImm2(ref invalid_imm, _) => {
-            format!(
-                "{}\n\nproblem: {}",
-                replace_last(&problem_line, invalid_imm, &invalid_imm.red().to_string()),
-                err.to_string().red()
-            )
-        }
-    });
-    problem.push_str(&format!("\ninstruction found: {}", make_instruction_number(*instruction).unwrap()));
-    problem
-}
\ No newline at end of file
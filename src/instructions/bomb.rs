@@ -1,36 +1,25 @@
-use std::fmt::{Display, Formatter};
-use regex::Regex;
-use crate::imm_dec_to_hex;
-use crate::instructions::{Instruction, ParseError};
-use crate::instructions::helpers::make_instruction_number;
-use crate::instructions::ParseError::{MissingImm1, RegexDoesNotMatch, UnsupportedImm1};
+use crate::instructions::{Parse, Slot, SpannedError};
+use crate::instructions::helpers::immediate;
+use crate::parser::Operand;
+use crate::symbols::Context;
 
+/// Operand of BOMB: a 16-bit `imm1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Bomb {
-    instruction_number: u8,
-    imm1: u32,
+    pub imm1: u16,
 }
 
-impl Instruction for Bomb {
-    type Instruction = Self;
-    fn parse(instruction: &str, regex: Regex, instruction_number: u8) -> Result<Self::Instruction, ParseError> {
-        return if let Some(captures) = regex.captures(instruction) {
-            let imm1_number = if let Some(imm1) = captures.get(1) {
-                let imm1 = imm1.as_str().to_owned();
-                if imm1.is_empty() { return Err(MissingImm1); }
-                let imm1_number = imm1.parse::<u32>().map_err(|_| UnsupportedImm1(imm1.clone(), 65536))?;
-                if imm1_number > 65535 { return Err(UnsupportedImm1(imm1, 65536)); }
-                imm1_number
-            } else { return Err(MissingImm1); };
+impl Parse for Bomb {
+    fn parse(operands: &[Operand], context: &Context) -> Result<Self, SpannedError> {
+        let imm1 = immediate(&operands[0], Slot::Imm1, 65535, context)? as u16;
 
-            Ok(Self {instruction_number, imm1: imm1_number})
-        } else {
-            Err(RegexDoesNotMatch)
-        };
+        Ok(Self { imm1 })
     }
 }
 
-impl Display for Bomb {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {} 00", make_instruction_number(self.instruction_number).unwrap(), imm_dec_to_hex!(self.imm1))
+impl Bomb {
+    pub fn encode(&self, opcode: u8) -> [u8; 4] {
+        let [high, low] = self.imm1.to_be_bytes();
+        [opcode, high, low, 0]
     }
 }
@@ -1,45 +1,71 @@
-use regex::Regex;
-
-pub fn make_instruction_number(number: u8) -> Option<String> {
-    if number < 10 {
-        Some(format!("0{number}"))
-    } else if number > 99 {
-        None
-    } else {
-        Some(format!("{number}"))
-    }
+use crate::instructions::{ParseError, Slot, SpannedError};
+use crate::expression::Expression;
+use crate::parser::{Operand, OperandKind};
+use crate::suggestions::did_you_mean;
+use crate::symbols::Context;
+
+pub fn encode_regs(opcode: u8, reg1: u8, reg2: u8, imm1: u16) -> [u8; 4] {
+    let [high, low] = imm1.to_be_bytes();
+    [opcode, (reg1 << 4) | (reg2 & 0x0F), high, low]
 }
 
-#[macro_export]
-macro_rules! imm_dec_to_hex {
-    ($imm_dec:expr) => {
-        format!("{:02X} {:02X}", ($imm_dec >> 8) & 0xFF, $imm_dec & 0xFF)
-    };
+pub fn format_word(word: &[u8; 4]) -> String {
+    format!("{:02X} {:02X} {:02X} {:02X}", word[0], word[1], word[2], word[3])
 }
 
-pub fn matches(instruction: &str, regexes: &Vec<Regex>) -> Option<Regex> {
-    for regex in regexes {
-        if regex.is_match(instruction) {
-            return Some(regex.clone());
+/// Register number of `operand`, which must be within `min..=max`.
+pub fn register(operand: &Operand, slot: Slot, min: u8, max: u8) -> Result<u8, SpannedError> {
+    match operand.kind {
+        OperandKind::Register(number) if (min..=max).contains(&number) => Ok(number),
+        _ => {
+            let error = if slot == Slot::Reg2 {
+                ParseError::UnsupportedReg2(operand.text.clone(), min, max)
+            } else {
+                ParseError::UnsupportedReg1(operand.text.clone(), min, max)
+            };
+            // A name where a register belongs is most likely a misspelled register.
+            let help = match &operand.kind {
+                OperandKind::Expression(Expression::Symbol(name, _)) => {
+                    let names: Vec<String> = (min..=max).map(|number| format!("reg{}", number)).collect();
+                    did_you_mean(name, names.iter().map(String::as_str))
+                }
+                _ => None,
+            };
+            Err(error.at(operand.span).with_help(help))
         }
     }
-    None
 }
 
-pub fn replace_first(input: &str, from: &str, to: &str) -> String {
-    if let Some(index) = input.find(from) {
-        let (before, after) = input.split_at(index);
-        format!("{}{}{}", before, to, &after[from.len()..])
-    } else {
-        input.to_string()
+/// Register number of an operand the instruction writes into, so it can not be reg0.
+pub fn destination_register(operand: &Operand) -> Result<u8, SpannedError> {
+    if operand.kind == OperandKind::Register(0) {
+        return Err(ParseError::CannotWriteIntoReg0.at(operand.span));
     }
+    register(operand, Slot::Reg1, 1, 5)
+}
+
+/// Value of an immediate `operand`, a literal or a constant or label name, which must be at most `max`.
+pub fn immediate(operand: &Operand, slot: Slot, max: u32, context: &Context) -> Result<u32, SpannedError> {
+    signed_immediate(operand, slot, 0, i64::from(max), context).map(|number| number as u32)
 }
 
-pub fn replace_last(input: &str, from: &str, to: &str) -> String {
-    if let Some(index) = input.rfind(from) {
-        let (before, after) = input.split_at(index);
-        format!("{}{}{}", before, to, &after[from.len()..])
-    } else {
-        input.to_string()
+/// 16-bit immediate whose bits are used as they are, so negative values are stored in two's complement.
+pub fn half_word(operand: &Operand, slot: Slot, context: &Context) -> Result<u16, SpannedError> {
+    signed_immediate(operand, slot, -32768, 65535, context).map(|number| number as u16)
+}
+
+/// Value of an immediate `operand`, which must be within `min..=max`.
+pub fn signed_immediate(operand: &Operand, slot: Slot, min: i64, max: i64, context: &Context) -> Result<i64, SpannedError> {
+    match context.symbols.value(operand)? {
+        Some(number) if (min..=max).contains(&number) => Ok(number),
+        _ => {
+            let error = if slot == Slot::Imm2 {
+                ParseError::UnsupportedImm2(operand.text.clone(), min, max)
+            } else {
+                ParseError::UnsupportedImm1(operand.text.clone(), min, max)
+            };
+            Err(error.at(operand.span))
+        }
     }
 }
+
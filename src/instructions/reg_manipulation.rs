@@ -1,55 +1,28 @@
-use std::fmt::{Display, Formatter};
-use regex::Regex;
-use crate::imm_dec_to_hex;
-use crate::instructions::{Instruction, ParseError};
-use crate::instructions::helpers::make_instruction_number;
-use crate::instructions::ParseError::{CannotWriteIntoReg0, MissingImm1, MissingReg1, MissingReg2, RegexDoesNotMatch, UnsupportedImm1, UnsupportedReg1, UnsupportedReg2};
+use crate::instructions::{Parse, Slot, SpannedError};
+use crate::instructions::helpers::{destination_register, encode_regs, immediate, register};
+use crate::parser::Operand;
+use crate::symbols::Context;
 
+/// Operands of ADD, SUB, MUL and MOV: `reg1 op= reg2 + imm1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RegManipulation {
-    instruction_number: u8,
-    reg1: u8,
-    reg2: u8,
-    imm1: u32,
+    pub reg1: u8,
+    pub reg2: u8,
+    pub imm1: u16,
 }
 
-impl Instruction for RegManipulation {
-    type Instruction = Self;
-    fn parse(instruction: &str, regex: Regex, instruction_number: u8) -> Result<Self::Instruction, ParseError> {
-        return if let Some(captures) = regex.captures(instruction) {
-            let reg1_number = if let Some(reg1) = captures.get(1) {
-                let reg1 = reg1.as_str().to_owned();
-                if reg1 == "reg0" { return Err(CannotWriteIntoReg0); }
-                if reg1.is_empty() { return Err(MissingReg1); }
-                let reg1_number = reg1.trim_start_matches("reg").parse::<u8>().map_err(|_| UnsupportedReg1(reg1.clone(), 1, 5))?;
-                if !(1..=5).contains(&reg1_number) { return Err(UnsupportedReg1(reg1, 1, 5)); }
-                reg1_number
-            } else { return Err(MissingReg1); };
+impl Parse for RegManipulation {
+    fn parse(operands: &[Operand], context: &Context) -> Result<Self, SpannedError> {
+        let reg1 = destination_register(&operands[0])?;
+        let reg2 = register(&operands[1], Slot::Reg2, 0, 5)?;
+        let imm1 = immediate(&operands[2], Slot::Imm1, 65535, context)? as u16;
 
-            let reg2_number = if let Some(reg2) = captures.get(2) {
-                let reg2 = reg2.as_str().to_owned();
-                if reg2.is_empty() { return Err(MissingReg2); }
-                let reg2_number = reg2.trim_start_matches("reg").parse::<u8>().map_err(|_| UnsupportedReg2(reg2.clone(), 0, 5))?;
-                if reg2_number > 5 { return Err(UnsupportedReg2(reg2, 0, 5)); }
-                reg2_number
-            } else { return Err(MissingReg2); };
-
-            let imm1_number = if let Some(imm1) = captures.get(3) {
-                let imm1 = imm1.as_str().to_owned();
-                if imm1.is_empty() { return Err(MissingImm1); }
-                let imm1_number = imm1.parse::<u32>().map_err(|_| UnsupportedImm1(imm1.clone(), 65536))?;
-                if imm1_number > 65535 { return Err(UnsupportedImm1(imm1, 65536)); }
-                imm1_number
-            } else { return Err(MissingImm1); };
-
-            Ok(Self {instruction_number, reg1: reg1_number, reg2: reg2_number, imm1: imm1_number})
-        } else {
-            Err(RegexDoesNotMatch)
-        };
+        Ok(Self { reg1, reg2, imm1 })
     }
 }
 
-impl Display for RegManipulation {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {}{} {}", make_instruction_number(self.instruction_number).unwrap(), self.reg1, self.reg2, imm_dec_to_hex!(self.imm1))
+impl RegManipulation {
+    pub fn encode(&self, opcode: u8) -> [u8; 4] {
+        encode_regs(opcode, self.reg1, self.reg2, self.imm1)
     }
 }
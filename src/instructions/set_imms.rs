@@ -1,46 +1,71 @@
-use std::fmt::{Display, Formatter};
-use regex::Regex;
-use crate::imm_dec_to_hex;
-use crate::instructions::{Instruction, ParseError};
-use crate::instructions::helpers::make_instruction_number;
-use crate::instructions::ParseError::{CannotWriteIntoReg0, MissingImm1, MissingReg1, RegexDoesNotMatch, UnsupportedImm1, UnsupportedReg1};
+use crate::instructions::{Instruction, Parse, Slot, SpannedError};
+use crate::instructions::helpers::{destination_register, encode_regs, half_word, signed_immediate};
+use crate::instructions::reg_manipulation::RegManipulation;
+use crate::parser::{LoadImmediateNode, Operand};
+use crate::symbols::Context;
 
+/// Operands of SETIMMLOW and SETIMMHIGH: `reg1[low|high] = imm1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SetImm {
-    instruction_number: u8,
-    reg1: u8,
-    imm1: u32,
+    pub reg1: u8,
+    pub imm1: u16,
 }
 
-impl Instruction for SetImm {
-    type Instruction = Self;
-    fn parse(instruction: &str, regex: Regex, instruction_number: u8) -> Result<Self::Instruction, ParseError> {
-        return if let Some(captures) = regex.captures(instruction) {
-            let reg1_number = if let Some(reg1) = captures.get(1) {
-                let reg1 = reg1.as_str().to_owned();
-                if reg1 == "reg0" { return Err(CannotWriteIntoReg0); }
-                if reg1.is_empty() { return Err(MissingReg1); }
-                let reg1_number = reg1.trim_start_matches("reg").parse::<u8>().map_err(|_| UnsupportedReg1(reg1.clone(), 1, 5))?;
-                if reg1_number > 5 { return Err(UnsupportedReg1(reg1, 1, 5)); }
-                reg1_number
-            } else { return Err(MissingReg1); };
-
-            let imm1_number = if let Some(imm1) = captures.get(2) {
-                let imm1 = imm1.as_str().to_owned();
-                if imm1.is_empty() { return Err(MissingImm1); }
-                let imm1_number = imm1.parse::<u32>().map_err(|_| UnsupportedImm1(imm1.clone(), 65536))?;
-                if imm1_number > 65535 { return Err(UnsupportedImm1(imm1, 65536)); }
-                imm1_number
-            } else { return Err(MissingImm1); };
-
-            Ok(Self {instruction_number, reg1: reg1_number, imm1: imm1_number})
-        } else {
-            Err(RegexDoesNotMatch)
-        };
+impl Parse for SetImm {
+    fn parse(operands: &[Operand], context: &Context) -> Result<Self, SpannedError> {
+        let reg1 = destination_register(&operands[0])?;
+        let imm1 = half_word(&operands[1], Slot::Imm1, context)?;
+
+        Ok(Self { reg1, imm1 })
+    }
+}
+
+impl SetImm {
+    pub fn encode(&self, opcode: u8) -> [u8; 4] {
+        encode_regs(opcode, self.reg1, 0, self.imm1)
     }
 }
 
-impl Display for SetImm {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} {}0 {}", make_instruction_number(self.instruction_number).unwrap(), self.reg1, imm_dec_to_hex!(self.imm1))
+/// The `LI reg1, value` pseudo-instruction, loading a full 32-bit value into `reg1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadImmediate {
+    pub reg1: u8,
+    pub value: u32,
+}
+
+impl LoadImmediate {
+    /// Negative values are stored in two's complement, like for the SETIMM instructions.
+    pub fn parse(node: &LoadImmediateNode, context: &Context) -> Result<Self, SpannedError> {
+        let reg1 = destination_register(&node.reg1)?;
+        let value = signed_immediate(&node.value, Slot::Imm1, -(1 << 31), i64::from(u32::MAX), context)?;
+
+        Ok(Self { reg1, value: value as u32 })
+    }
+
+    /// Number of words `LI` takes for `value`, or for a value that is not known yet.
+    ///
+    /// SETIMMLOW keeps the high half of the register, so a value without one is loaded with a
+    /// single `MOV reg1 = reg0 + value` instead, which clears it.
+    pub fn size(value: Option<i64>) -> u32 {
+        match value {
+            Some(value) if (0..=0xFFFF).contains(&value) => 1,
+            _ => 2,
+        }
+    }
+
+    /// The instructions `LI` expands into, given the `size` it was assigned in the first pass.
+    pub fn expand(&self, size: u32) -> Vec<Instruction> {
+        let low = self.value as u16;
+        // The one-word form is on purpose: MOV puts reg0 + imm1 into reg1, and reg0 is always 0, so reg1 ends
+        // up as the zero-extended value, which is what SETIMMLOW followed by SETIMMHIGH 0 would leave, in
+        // half the words.
+        if size == 1 {
+            return vec![Instruction::Mov(RegManipulation { reg1: self.reg1, reg2: 0, imm1: low })];
+        }
+
+        vec![
+            Instruction::SetImmLow(SetImm { reg1: self.reg1, imm1: low }),
+            Instruction::SetImmHigh(SetImm { reg1: self.reg1, imm1: (self.value >> 16) as u16 }),
+        ]
     }
 }
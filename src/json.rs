@@ -0,0 +1,20 @@
+//! Just enough JSON writing for the crate's machine-readable outputs, without pulling in serde.
+
+/// `text` as a quoted JSON string.
+pub fn string(text: &str) -> String {
+    let mut output = String::with_capacity(text.len() + 2);
+    output.push('"');
+    for char in text.chars() {
+        match char {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            char if char.is_control() => output.push_str(&format!("\\u{:04x}", char as u32)),
+            char => output.push(char),
+        }
+    }
+    output.push('"');
+    output
+}
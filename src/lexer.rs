@@ -0,0 +1,326 @@
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Location of a piece of source: byte range plus 1-based line and column.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    /// Byte offset of the first character in the whole source.
+    pub start: usize,
+    /// Byte offset one past the last character in the whole source.
+    pub end: usize,
+    pub line: usize,
+    /// 1-based byte column of `start` within its line.
+    pub column: usize,
+}
+
+impl Span {
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end.max(self.end), line: self.line, column: self.column }
+    }
+
+    /// Empty span right after `self`.
+    pub fn after(self) -> Span {
+        Span { start: self.end, end: self.end, line: self.line, column: self.column + (self.end - self.start) }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenKind {
+    Identifier(String),
+    /// `.name`, the name including the dot.
+    Directive(String),
+    Register(u8),
+    /// Numeric or character literal exactly as written, see [`number_value`].
+    Number(String),
+    /// `"text"`, holding the text with escapes resolved, see [`string_value`].
+    String(String),
+    Comment(String),
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Colon,
+    At,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    Bang,
+    AndAnd,
+    OrOr,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    EqualEqual,
+    NotEqual,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    Unknown(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+    /// The token exactly as written in the source.
+    pub text: String,
+}
+
+impl Display for TokenKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenKind::Identifier(identifier) => write!(f, "{}", identifier),
+            TokenKind::Directive(directive) => write!(f, "{}", directive),
+            TokenKind::Register(number) => write!(f, "reg{}", number),
+            TokenKind::Number(number) => write!(f, "{}", number),
+            TokenKind::String(string) => write!(f, "{:?}", string),
+            TokenKind::Comment(comment) => write!(f, ";{}", comment),
+            TokenKind::LeftParen => write!(f, "("),
+            TokenKind::RightParen => write!(f, ")"),
+            TokenKind::LeftBracket => write!(f, "["),
+            TokenKind::RightBracket => write!(f, "]"),
+            TokenKind::Comma => write!(f, ","),
+            TokenKind::Colon => write!(f, ":"),
+            TokenKind::At => write!(f, "@"),
+            TokenKind::Plus => write!(f, "+"),
+            TokenKind::Minus => write!(f, "-"),
+            TokenKind::Star => write!(f, "*"),
+            TokenKind::Slash => write!(f, "/"),
+            TokenKind::Percent => write!(f, "%"),
+            TokenKind::Ampersand => write!(f, "&"),
+            TokenKind::Pipe => write!(f, "|"),
+            TokenKind::Caret => write!(f, "^"),
+            TokenKind::Tilde => write!(f, "~"),
+            TokenKind::Bang => write!(f, "!"),
+            TokenKind::AndAnd => write!(f, "&&"),
+            TokenKind::OrOr => write!(f, "||"),
+            TokenKind::Less => write!(f, "<"),
+            TokenKind::LessEqual => write!(f, "<="),
+            TokenKind::Greater => write!(f, ">"),
+            TokenKind::GreaterEqual => write!(f, ">="),
+            TokenKind::ShiftLeft => write!(f, "<<"),
+            TokenKind::ShiftRight => write!(f, ">>"),
+            TokenKind::Equal => write!(f, "="),
+            TokenKind::EqualEqual => write!(f, "=="),
+            TokenKind::NotEqual => write!(f, "!="),
+            TokenKind::PlusEqual => write!(f, "+="),
+            TokenKind::MinusEqual => write!(f, "-="),
+            TokenKind::StarEqual => write!(f, "*="),
+            TokenKind::Unknown(character) => write!(f, "{}", character),
+        }
+    }
+}
+
+/// Splits one source line into tokens.
+///
+/// `line` is the 1-based line number and `offset` the byte offset of the
+/// line's first character in the whole source; both end up in every [`Span`].
+pub fn tokenize(text: &str, line: usize, offset: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, character)) = chars.next() {
+        if character.is_whitespace() {
+            continue;
+        }
+
+        let mut end = start + character.len_utf8();
+
+        let kind = match character {
+            ';' => {
+                end = text.len();
+                TokenKind::Comment(text[start + 1..].to_owned())
+            }
+            '\'' => {
+                let mut escaped = false;
+                for (index, next) in chars.by_ref() {
+                    end = index + next.len_utf8();
+                    if next == '\'' && !escaped { break; }
+                    escaped = next == '\\' && !escaped;
+                }
+                TokenKind::Number(text[start..end].to_owned())
+            }
+            '"' => {
+                let mut escaped = false;
+                end = text.len();
+                for (index, next) in chars.by_ref() {
+                    if next == '"' && !escaped {
+                        end = index + 1;
+                        break;
+                    }
+                    escaped = next == '\\' && !escaped;
+                }
+                match string_value(&text[start..end]) {
+                    Some(string) => TokenKind::String(string),
+                    None => TokenKind::Unknown('"'),
+                }
+            }
+            '0'..='9' => {
+                take_while(&mut chars, &mut end, |next| next.is_ascii_alphanumeric() || next == '_');
+                TokenKind::Number(text[start..end].to_owned())
+            }
+            _ if character.is_alphabetic() || character == '_' => {
+                take_while(&mut chars, &mut end, |next| next.is_alphanumeric() || next == '_');
+                let word = &text[start..end];
+                match word.strip_prefix("reg").and_then(|number| number.parse::<u8>().ok()) {
+                    Some(number) => TokenKind::Register(number),
+                    None => TokenKind::Identifier(word.to_owned()),
+                }
+            }
+            '.' if matches!(chars.peek(), Some(&(_, next)) if next.is_alphabetic()) => {
+                take_while(&mut chars, &mut end, |next| next.is_alphanumeric() || next == '_');
+                TokenKind::Directive(text[start..end].to_owned())
+            }
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '[' => TokenKind::LeftBracket,
+            ']' => TokenKind::RightBracket,
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '@' => TokenKind::At,
+            '+' | '-' | '*' | '=' | '!' => {
+                let compound = matches!(chars.peek(), Some(&(_, '=')));
+                if compound {
+                    end += 1;
+                    chars.next();
+                }
+                match (character, compound) {
+                    ('+', false) => TokenKind::Plus,
+                    ('+', true) => TokenKind::PlusEqual,
+                    ('-', false) => TokenKind::Minus,
+                    ('-', true) => TokenKind::MinusEqual,
+                    ('*', false) => TokenKind::Star,
+                    ('*', true) => TokenKind::StarEqual,
+                    ('=', false) => TokenKind::Equal,
+                    ('=', true) => TokenKind::EqualEqual,
+                    ('!', true) => TokenKind::NotEqual,
+                    _ => TokenKind::Bang,
+                }
+            }
+            '<' | '>' => {
+                let second = chars.next_if(|&(_, next)| next == character || next == '=').map(|(_, next)| next);
+                if second.is_some() {
+                    end += 1;
+                }
+                match (character, second) {
+                    ('<', None) => TokenKind::Less,
+                    ('<', Some('=')) => TokenKind::LessEqual,
+                    ('<', Some(_)) => TokenKind::ShiftLeft,
+                    ('>', None) => TokenKind::Greater,
+                    ('>', Some('=')) => TokenKind::GreaterEqual,
+                    _ => TokenKind::ShiftRight,
+                }
+            }
+            '&' | '|' => {
+                let doubled = chars.next_if(|&(_, next)| next == character).is_some();
+                if doubled {
+                    end += 1;
+                }
+                match (character, doubled) {
+                    ('&', false) => TokenKind::Ampersand,
+                    ('&', true) => TokenKind::AndAnd,
+                    ('|', false) => TokenKind::Pipe,
+                    _ => TokenKind::OrOr,
+                }
+            }
+            '/' => TokenKind::Slash,
+            '%' => TokenKind::Percent,
+            '^' => TokenKind::Caret,
+            '~' => TokenKind::Tilde,
+            _ => TokenKind::Unknown(character),
+        };
+
+        tokens.push(Token {
+            kind,
+            span: Span { start: offset + start, end: offset + end, line, column: start + 1 },
+            text: text[start..end].to_owned(),
+        });
+
+        if end == text.len() {
+            break;
+        }
+    }
+
+    tokens
+}
+
+fn take_while(chars: &mut Peekable<CharIndices>, end: &mut usize, predicate: fn(char) -> bool) {
+    while let Some(&(index, next)) = chars.peek() {
+        if !predicate(next) { break; }
+        *end = index + next.len_utf8();
+        chars.next();
+    }
+}
+
+/// Value of a literal as written in the source.
+///
+/// Accepts decimal, `0x` hex, `0b` binary and `0o` octal numbers with `_`
+/// digit separators, an optional leading `-`, and character literals such as
+/// `'A'` or `'\n'`.
+pub fn number_value(literal: &str) -> Option<i64> {
+    if let Some(positive) = literal.strip_prefix('-') {
+        return number_value(positive).map(|value| -value);
+    }
+
+    if let Some(character) = literal.strip_prefix('\'').and_then(|rest| rest.strip_suffix('\'')) {
+        let mut chars = character.chars();
+        let value = match (chars.next()?, chars.next(), chars.next()) {
+            ('\\', Some(escaped), None) => escape(escaped)?,
+            (character, None, None) => character,
+            _ => return None,
+        };
+        return Some(i64::from(u32::from(value)));
+    }
+
+    let (digits, radix) = match literal.get(..2) {
+        Some("0x" | "0X") => (&literal[2..], 16),
+        Some("0b" | "0B") => (&literal[2..], 2),
+        Some("0o" | "0O") => (&literal[2..], 8),
+        _ => (literal, 10),
+    };
+
+    let digits: String = digits.chars().filter(|&digit| digit != '_').collect();
+    if digits.is_empty() || digits.starts_with(['+', '-']) {
+        return None;
+    }
+    i64::from_str_radix(&digits, radix).ok()
+}
+
+/// Text of a string literal such as `"lib/common.sop"` or `"a\tb\n"`, `None` if it is not a
+/// complete literal or uses an unknown escape.
+///
+/// Strings accept the same escapes as character literals.
+pub fn string_value(literal: &str) -> Option<String> {
+    let inner = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut string = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(character) = chars.next() {
+        string.push(if character == '\\' { escape(chars.next()?)? } else { character });
+    }
+    Some(string)
+}
+
+/// Character that `\escaped` stands for in character and string literals.
+fn escape(escaped: char) -> Option<char> {
+    match escaped {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '\'' | '"' => Some(escaped),
+        _ => None,
+    }
+}
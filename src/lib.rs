@@ -0,0 +1,35 @@
+//! Assembler for the Sopt language used in the FIKS competition.
+//!
+//! [`assemble`] turns Sopt source into a [`Program`], which can be encoded
+//! into 4-byte machine words and written out in any [`Format`].
+//! [`disassemble`] goes the other way, from `.tik` text back to source, and
+//! [`emulator::run`] executes a program on the Sopt virtual machine.
+
+pub mod assembler;
+pub mod conditionals;
+pub mod data;
+pub mod diagnostics;
+pub mod disassembler;
+pub mod emulator;
+pub mod expression;
+pub mod formats;
+pub mod formatter;
+pub mod instructions;
+pub mod json;
+pub mod lexer;
+pub mod lints;
+pub mod listing;
+pub mod parser;
+pub mod preprocessor;
+pub mod program;
+pub mod repeat;
+pub mod suggestions;
+pub mod symbol_map;
+pub mod symbols;
+
+pub use crate::assembler::{assemble, assemble_with, Options};
+pub use crate::diagnostics::{Diagnostic, Diagnostics};
+pub use crate::disassembler::{disassemble, Style};
+pub use crate::formats::Format;
+pub use crate::instructions::Instruction;
+pub use crate::program::{Program, Word};
@@ -0,0 +1,503 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::emulator::DEFAULT_MEMORY_SIZE;
+use crate::instructions::jumps::Jump;
+use crate::instructions::{Instruction, ParseError};
+use crate::lexer::{tokenize, Span, TokenKind};
+use crate::program::{Line, Program, Word};
+
+/// A kind of mistake that still assembles, which can be allowed, warned about or denied on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A jump to an address before the program or past its end.
+    JumpOutsideProgram,
+    /// Instructions that nothing leads to, like the ones after `if (reg0 == reg0) pc += 3`.
+    UnreachableCode,
+    /// A `<` or `!=` jump that compares a register with itself.
+    NeverTaken,
+    /// A register that is read but never written, so it is always 0.
+    UnwrittenRegister,
+    /// A LOAD or STORE of an address that is known to be past the end of memory.
+    MemoryOutOfBounds,
+    /// A program without any instruction besides NOP.
+    OnlyNops,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::JumpOutsideProgram,
+        Lint::UnreachableCode,
+        Lint::NeverTaken,
+        Lint::UnwrittenRegister,
+        Lint::MemoryOutOfBounds,
+        Lint::OnlyNops,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::JumpOutsideProgram => "jump-outside-program",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::NeverTaken => "never-taken",
+            Lint::UnwrittenRegister => "unwritten-register",
+            Lint::MemoryOutOfBounds => "memory-out-of-bounds",
+            Lint::OnlyNops => "only-nops",
+        }
+    }
+
+    /// Stable code of the lint's warnings, like `W0002`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Lint::JumpOutsideProgram => "W0001",
+            Lint::UnreachableCode => "W0002",
+            Lint::NeverTaken => "W0003",
+            Lint::UnwrittenRegister => "W0004",
+            Lint::MemoryOutOfBounds => "W0005",
+            Lint::OnlyNops => "W0006",
+        }
+    }
+}
+
+/// What is done with the problems a lint finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Level {
+    /// Not reported.
+    Allow,
+    #[default]
+    Warn,
+    /// Reported as an error.
+    Deny,
+}
+
+/// The [`Level`] of every lint, all [`Level::Warn`] unless changed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LintLevels {
+    levels: [Level; Lint::ALL.len()],
+}
+
+impl LintLevels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels[lint as usize] = level;
+    }
+
+    pub fn set_all(&mut self, level: Level) {
+        self.levels = [level; Lint::ALL.len()];
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels[lint as usize]
+    }
+}
+
+/// A problem found by a lint, reported as a [`ParseError::Lint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A jump to `target` in a program of `length` words.
+    JumpOutsideProgram { target: i64, length: usize },
+    UnreachableCode,
+    /// `if (reg comparison reg)` with the same register on both sides.
+    NeverTaken { reg: u8, comparison: &'static str },
+    UnwrittenRegister(u8),
+    /// A LOAD or STORE of `address` in a memory of `size` words.
+    MemoryOutOfBounds { address: i64, size: usize },
+    OnlyNops,
+}
+
+impl Warning {
+    pub fn lint(&self) -> Lint {
+        match self {
+            Warning::JumpOutsideProgram { .. } => Lint::JumpOutsideProgram,
+            Warning::UnreachableCode => Lint::UnreachableCode,
+            Warning::NeverTaken { .. } => Lint::NeverTaken,
+            Warning::UnwrittenRegister(_) => Lint::UnwrittenRegister,
+            Warning::MemoryOutOfBounds { .. } => Lint::MemoryOutOfBounds,
+            Warning::OnlyNops => Lint::OnlyNops,
+        }
+    }
+
+    /// Advice on fixing the problem, for the warnings where the message alone may not be enough.
+    pub fn help(&self) -> Option<String> {
+        match self {
+            Warning::JumpOutsideProgram { length, .. } => Some(format!("a jump to address {} is the way to end the program", length)),
+            Warning::UnwrittenRegister(_) => Some("registers start at 0, use reg0 where 0 is meant".to_owned()),
+            Warning::MemoryOutOfBounds { size, .. } => Some(format!("memory has {} words, from address 0 to {}", size, size - 1)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::JumpOutsideProgram { target, length } => write!(f, "jump to address {} is outside the program of {} words", target, length),
+            Warning::UnreachableCode => write!(f, "unreachable code"),
+            Warning::NeverTaken { reg, comparison } => write!(f, "`if (reg{} {} reg{})` is never taken", reg, comparison, reg),
+            Warning::UnwrittenRegister(reg) => write!(f, "reg{} is read but never written, so it is always 0", reg),
+            Warning::MemoryOutOfBounds { address, .. } => write!(f, "address {} is outside memory", address),
+            Warning::OnlyNops => write!(f, "the program has no instructions besides NOP"),
+        }
+    }
+}
+
+/// A warning along with the line and instruction it was found at.
+struct Finding<'a> {
+    line: &'a Line,
+    instruction: Option<&'a Instruction>,
+    warning: Warning,
+}
+
+/// Looks for mistakes in `program` that still let it assemble, reporting them at the given `levels`.
+///
+/// Jumps are relative to the program counter, so every jump target is known and only which way a branch
+/// goes is not. Register values are followed through straight-line code only, which is enough for an
+/// address loaded into a register right before a LOAD or STORE.
+pub fn lint(program: &Program, levels: &LintLevels) -> Diagnostics {
+    // The words in address order, which is program order since `.org` and `.align` gaps are filled.
+    let words: Vec<(&Line, &Word)> = program.lines.iter().flat_map(|line| line.words.iter().map(move |word| (line, word))).collect();
+    // What the CPU runs at each address. It does not tell data from instructions, so it runs through
+    // `.org` and `.align` fill, which is NOP unless changed, like through any other word that decodes.
+    let executed: Vec<Option<Instruction>> = words
+        .iter()
+        .map(|(_, word)| match word {
+            Word::Instruction(instruction) => Some(*instruction),
+            Word::Data(data) => Instruction::decode(*data),
+        })
+        .collect();
+
+    let mut findings = Vec::new();
+    jumps(&words, &mut findings);
+    unreachable_code(program, &executed, &mut findings);
+    unwritten_registers(&words, &mut findings);
+    memory_accesses(&words, &executed, &mut findings);
+    only_nops(&words, &mut findings);
+
+    let mut diagnostics = Diagnostics::new();
+    for Finding { line, instruction, warning } in findings {
+        let severity = match levels.level(warning.lint()) {
+            Level::Allow => continue,
+            Level::Warn => Severity::Warning,
+            Level::Deny => Severity::Error,
+        };
+        diagnostics.push(Diagnostic {
+            severity,
+            file: line.file.clone(),
+            line: line.number,
+            text: line.text.clone(),
+            span: statement_span(line),
+            opcode: instruction.map(Instruction::opcode),
+            help: warning.help(),
+            error: ParseError::Lint(warning),
+            expansions: line.expansions.clone(),
+        }
+        .written(&line.renames));
+    }
+    diagnostics.sort();
+    diagnostics
+}
+
+/// The jump at `address` if `instruction` is one, with its target and whether it is always taken (`Some(true)`),
+/// never taken (`Some(false)`) or depends on the registers (`None`).
+fn jump(address: usize, instruction: &Instruction) -> Option<(&Jump, i64, Option<bool>)> {
+    let (jump, forward) = match instruction {
+        Instruction::Jump(jump) | Instruction::LtJump(jump) | Instruction::NeqJump(jump) => (jump, true),
+        Instruction::RevJump(jump) | Instruction::RevLtJump(jump) | Instruction::RevNeqJump(jump) => (jump, false),
+        _ => return None,
+    };
+    let offset = i64::from(jump.imm1);
+    let target = if forward { address as i64 + offset } else { address as i64 - offset };
+    // A register always equals itself, and is never less than or different from itself.
+    let taken = (jump.reg1 == jump.reg2).then_some(matches!(instruction, Instruction::Jump(_) | Instruction::RevJump(_)));
+    Some((jump, target, taken))
+}
+
+/// Jumps that are never taken, and jumps to an address outside the program.
+fn jumps<'a>(words: &[(&'a Line, &'a Word)], findings: &mut Vec<Finding<'a>>) {
+    for (address, &(line, word)) in words.iter().enumerate() {
+        let Word::Instruction(instruction) = word else { continue };
+        let Some((jump, target, taken)) = jump(address, instruction) else { continue };
+
+        let warning = if taken == Some(false) {
+            let comparison = if matches!(instruction, Instruction::LtJump(_) | Instruction::RevLtJump(_)) { "<" } else { "!=" };
+            Warning::NeverTaken { reg: jump.reg1, comparison }
+        } else if !(0..=words.len() as i64).contains(&target) {
+            // Jumping right past the last word ends the program, like running into the end does.
+            Warning::JumpOutsideProgram { target, length: words.len() }
+        } else {
+            continue;
+        };
+        findings.push(Finding { line, instruction: Some(instruction), warning });
+    }
+}
+
+/// Lines of instructions that execution can not reach from address 0, reporting only the first line of each run.
+fn unreachable_code<'a>(program: &'a Program, executed: &[Option<Instruction>], findings: &mut Vec<Finding<'a>>) {
+    let mut reachable = vec![false; executed.len()];
+    let mut pending = vec![0];
+    while let Some(address) = pending.pop() {
+        if address >= executed.len() || reachable[address] {
+            continue;
+        }
+        reachable[address] = true;
+        // Data that does not decode stops the CPU, so nothing after it is reached through it.
+        let Some(instruction) = &executed[address] else { continue };
+        let next = match jump(address, instruction) {
+            Some((_, target, Some(true))) => vec![target],
+            Some((_, target, None)) => vec![address as i64 + 1, target],
+            _ => vec![address as i64 + 1],
+        };
+        pending.extend(next.into_iter().filter_map(|address| usize::try_from(address).ok()));
+    }
+
+    let mut in_run = false;
+    for line in &program.lines {
+        if !line.words.iter().any(|word| matches!(word, Word::Instruction(_))) {
+            continue;
+        }
+        let start = line.address as usize;
+        if reachable[start..start + line.words.len()].iter().any(|&reachable| reachable) {
+            in_run = false;
+        } else if !in_run {
+            findings.push(Finding { line, instruction: None, warning: Warning::UnreachableCode });
+            in_run = true;
+        }
+    }
+}
+
+/// Registers an instruction reads and the one it writes.
+fn registers(instruction: &Instruction) -> (Vec<u8>, Option<u8>) {
+    match instruction {
+        Instruction::Add(reg) | Instruction::Sub(reg) | Instruction::Mul(reg) => (vec![reg.reg1, reg.reg2], Some(reg.reg1)),
+        Instruction::Mov(reg) => (vec![reg.reg2], Some(reg.reg1)),
+        Instruction::Load(mem) => (vec![mem.reg2], Some(mem.reg1)),
+        Instruction::Store(mem) => (vec![mem.reg1, mem.reg2], None),
+        Instruction::Jump(jump) | Instruction::RevJump(jump) | Instruction::LtJump(jump)
+        | Instruction::RevLtJump(jump) | Instruction::NeqJump(jump) | Instruction::RevNeqJump(jump) => (vec![jump.reg1, jump.reg2], None),
+        // Setting one half keeps the other, so the register is read too.
+        Instruction::SetImmLow(set_imm) | Instruction::SetImmHigh(set_imm) => (vec![set_imm.reg1], Some(set_imm.reg1)),
+        Instruction::Nop | Instruction::Teleport(_) | Instruction::Bomb(_) => (Vec::new(), None),
+    }
+}
+
+/// The first read of every register other than reg0 that no instruction writes.
+fn unwritten_registers<'a>(words: &[(&'a Line, &'a Word)], findings: &mut Vec<Finding<'a>>) {
+    let instructions: Vec<(&Line, &Instruction)> = words
+        .iter()
+        .filter_map(|&(line, word)| match word {
+            Word::Instruction(instruction) => Some((line, instruction)),
+            Word::Data(_) => None,
+        })
+        .collect();
+
+    let mut written = [false; 16];
+    written[0] = true;
+    for (_, instruction) in &instructions {
+        if let (_, Some(reg)) = registers(instruction) {
+            written[reg as usize] = true;
+        }
+    }
+
+    for (line, instruction) in instructions {
+        for reg in registers(instruction).0 {
+            if !written[reg as usize] {
+                written[reg as usize] = true;
+                findings.push(Finding { line, instruction: Some(instruction), warning: Warning::UnwrittenRegister(reg) });
+            }
+        }
+    }
+}
+
+/// LOADs and STOREs past the end of memory, through a register whose value is known at that point.
+///
+/// Values are known from the start of the program, where every register is 0, and after each instruction
+/// that computes one from known values, data words that decode included. A jump target or a data word
+/// that does not decode forgets all of them but reg0, since execution may arrive there from anywhere.
+fn memory_accesses<'a>(words: &[(&'a Line, &'a Word)], executed: &[Option<Instruction>], findings: &mut Vec<Finding<'a>>) {
+    let size = DEFAULT_MEMORY_SIZE.max(words.len());
+    let targets: HashSet<i64> = executed
+        .iter()
+        .enumerate()
+        .filter_map(|(address, instruction)| jump(address, instruction.as_ref()?).filter(|(_, _, taken)| *taken != Some(false)))
+        .map(|(_, target, _)| target)
+        .collect();
+
+    let unknown = {
+        let mut known = [None; 16];
+        known[0] = Some(0);
+        known
+    };
+    let mut known: [Option<u32>; 16] = [Some(0); 16];
+    for (address, (&(line, word), instruction)) in words.iter().zip(executed).enumerate() {
+        if targets.contains(&(address as i64)) {
+            known = unknown;
+        }
+        let Some(instruction) = instruction else {
+            known = unknown;
+            continue;
+        };
+
+        let (reg, value) = match instruction {
+            Instruction::Add(reg) | Instruction::Sub(reg) | Instruction::Mul(reg) | Instruction::Mov(reg) => {
+                let operand = known[reg.reg2 as usize].map(|value| value.wrapping_add(u32::from(reg.imm1)));
+                let current = known[reg.reg1 as usize];
+                let value = match instruction {
+                    Instruction::Add(_) => current.zip(operand).map(|(current, operand)| current.wrapping_add(operand)),
+                    Instruction::Sub(_) => current.zip(operand).map(|(current, operand)| current.wrapping_sub(operand)),
+                    Instruction::Mul(_) => current.zip(operand).map(|(current, operand)| current.wrapping_mul(operand)),
+                    _ => operand,
+                };
+                (reg.reg1, value)
+            }
+            Instruction::Load(mem) | Instruction::Store(mem) => {
+                if let Some(base) = known[mem.reg2 as usize] {
+                    let address = i64::from(base) + i64::from(mem.imm1);
+                    // Only written instructions are reported, data that happens to decode to one is not.
+                    if let (true, Word::Instruction(written)) = (address >= size as i64, word) {
+                        findings.push(Finding { line, instruction: Some(written), warning: Warning::MemoryOutOfBounds { address, size } });
+                    }
+                }
+                match instruction {
+                    Instruction::Load(_) => (mem.reg1, None),
+                    _ => continue,
+                }
+            }
+            Instruction::SetImmLow(set_imm) => {
+                (set_imm.reg1, known[set_imm.reg1 as usize].map(|value| (value & 0xFFFF_0000) | u32::from(set_imm.imm1)))
+            }
+            Instruction::SetImmHigh(set_imm) => {
+                (set_imm.reg1, known[set_imm.reg1 as usize].map(|value| (value & 0x0000_FFFF) | (u32::from(set_imm.imm1) << 16)))
+            }
+            _ => continue,
+        };
+        if reg != 0 {
+            known[reg as usize] = value;
+        }
+    }
+}
+
+/// A program whose instructions are all NOPs, reported at the first one.
+fn only_nops<'a>(words: &[(&'a Line, &'a Word)], findings: &mut Vec<Finding<'a>>) {
+    let mut instructions = words.iter().filter_map(|&(line, word)| match word {
+        Word::Instruction(instruction) => Some((line, instruction)),
+        Word::Data(_) => None,
+    });
+    let Some((line, first)) = instructions.next() else { return };
+    if *first == Instruction::Nop && instructions.all(|(_, instruction)| *instruction == Instruction::Nop) {
+        findings.push(Finding { line, instruction: None, warning: Warning::OnlyNops });
+    }
+}
+
+/// Span of the statement on `line`, after its label and before its comment.
+fn statement_span(line: &Line) -> Span {
+    let tokens: Vec<_> = tokenize(&line.text, line.number, 0)
+        .into_iter()
+        .filter(|token| !matches!(token.kind, TokenKind::Comment(_)))
+        .collect();
+    let statement = match tokens.as_slice() {
+        [name, colon, rest @ ..] if matches!(name.kind, TokenKind::Identifier(_)) && colon.kind == TokenKind::Colon && !rest.is_empty() => rest,
+        tokens => tokens,
+    };
+    match (statement.first(), statement.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span { start: 0, end: 0, line: line.number, column: 1 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{assemble_with, Options};
+
+    /// Codes and lines of what the lints find in `source` at `levels`.
+    fn findings_with(source: &str, options: &Options, levels: &LintLevels) -> Vec<(&'static str, usize)> {
+        let program = assemble_with(source, options).expect(source);
+        lint(&program, levels).iter().map(|diagnostic| (diagnostic.error.code(), diagnostic.line)).collect()
+    }
+
+    /// Lines at which `lint` warns about `source`.
+    fn lines(source: &str, lint: Lint) -> Vec<usize> {
+        let mut levels = LintLevels::new();
+        levels.set_all(Level::Allow);
+        levels.set(lint, Level::Warn);
+        findings_with(source, &Options::default(), &levels).into_iter().map(|(_, line)| line).collect()
+    }
+
+    #[test]
+    fn jump_outside_program() {
+        assert_eq!(lines("NOP\nif (reg0 == reg0) pc += 5", Lint::JumpOutsideProgram), [2]);
+        assert_eq!(lines("NOP\nif (reg0 == reg1) pc -= 2", Lint::JumpOutsideProgram), [2]);
+        assert_eq!(lines("NOP\nif (reg0 == reg0) pc += 1", Lint::JumpOutsideProgram), []);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        let source = "if (reg0 == reg0) pc += 3\nreg1 = 1\nreg2 = 2\nNOP";
+        assert_eq!(lines(source, Lint::UnreachableCode), [2]);
+        assert_eq!(lines(&source.replace("reg0 == reg0", "reg0 == reg1"), Lint::UnreachableCode), []);
+    }
+
+    #[test]
+    fn never_taken() {
+        assert_eq!(lines("reg1 = 1\nif (reg1 < reg1) pc += 1\nif (reg1 != reg1) pc -= 1", Lint::NeverTaken), [2, 3]);
+        assert_eq!(lines("reg1 = 1\nif (reg1 < reg0) pc += 1\nif (reg1 == reg1) pc += 1", Lint::NeverTaken), []);
+    }
+
+    #[test]
+    fn unwritten_register() {
+        assert_eq!(lines("reg2 = reg1 + 1\nreg3 = reg1 + 2", Lint::UnwrittenRegister), [1]);
+        assert_eq!(lines("reg1 = 1\nreg2 = reg1 + 1", Lint::UnwrittenRegister), []);
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        assert_eq!(lines("reg1 = 0xFFFF\nreg2 = mem[reg1 + 1]", Lint::MemoryOutOfBounds), [2]);
+        assert_eq!(lines("reg1 = 0xFFFF\nreg2 = mem[reg1 + 0]", Lint::MemoryOutOfBounds), []);
+        // Past a jump target reg1 may hold anything.
+        assert_eq!(lines("reg1 = 0xFFFF\nloop: reg2 = mem[reg1 + 1]\nif (reg2 != reg0) goto loop", Lint::MemoryOutOfBounds), []);
+    }
+
+    #[test]
+    fn only_nops() {
+        assert_eq!(lines("NOP\nNOP\n.word 1", Lint::OnlyNops), [1]);
+        assert_eq!(lines("NOP\nreg1 = 1", Lint::OnlyNops), []);
+    }
+
+    #[test]
+    fn execution_runs_through_fill() {
+        let source = "reg1 = 1\n.org 4\nreg2 = 2";
+        let mut levels = LintLevels::new();
+        levels.set_all(Level::Allow);
+        levels.set(Lint::UnreachableCode, Level::Warn);
+        assert_eq!(findings_with(source, &Options::default(), &levels), []);
+        assert_eq!(findings_with(&source.replace(".org 4", ".align 4"), &Options::default(), &levels), []);
+
+        // A fill that does not decode stops the CPU before the code after the gap.
+        let options = Options { fill: [0xFF; 4], ..Options::default() };
+        assert_eq!(findings_with(source, &options, &levels), [("W0002", 3)]);
+    }
+
+    #[test]
+    fn levels() {
+        let source = "reg2 = reg1 + 1\nNOP";
+        let program = assemble_with(source, &Options::default()).unwrap();
+        let mut levels = LintLevels::new();
+
+        let warned = lint(&program, &levels);
+        assert_eq!((warned.count(Severity::Warning), warned.count(Severity::Error)), (1, 0));
+
+        levels.set(Lint::UnwrittenRegister, Level::Deny);
+        let denied = lint(&program, &levels);
+        assert_eq!((denied.count(Severity::Warning), denied.count(Severity::Error)), (0, 1));
+        assert_eq!(denied.iter().next().unwrap().error.code(), "W0004");
+
+        levels.set(Lint::UnwrittenRegister, Level::Allow);
+        assert!(lint(&program, &levels).is_empty());
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt::Write;
+use crate::program::{Program, Word};
+use crate::symbols::SymbolKind;
+
+/// Formats an assembly listing of `program`.
+///
+/// Every source line is printed with its line number, the address and encoding of each of its
+/// words and the original text. Lines without words show an address only when they define a label,
+/// and lines expanded from a macro have a `+` after their line number. A table of all labels and
+/// constants follows.
+pub fn listing(program: &Program) -> String {
+    let mut output = String::new();
+    let number_width = program.lines.iter().map(|line| line.number).max().unwrap_or(0).to_string().len().max(4) + 1;
+
+    writeln!(output, "{:>number_width$}  addr  bytes        source", "line ").unwrap();
+    for line in &program.lines {
+        let number = format!("{}{}", line.number, if line.expansions.is_empty() { " " } else { "+" });
+        let mut words = line.words.iter().map(Word::to_string);
+
+        let row = match words.next() {
+            Some(word) => format!("{:>number_width$}  {:04X}  {}  {}", number, line.address, word, line.text),
+            None if line.label.is_some() => format!("{:>number_width$}  {:04X}  {:11}  {}", number, line.address, "", line.text),
+            None => format!("{:>number_width$}  {:4}  {:11}  {}", number, "", "", line.text),
+        };
+        writeln!(output, "{}", row.trim_end()).unwrap();
+
+        for (index, word) in words.enumerate() {
+            writeln!(output, "{:>number_width$}  {:04X}  {}", "", line.address + index as u32 + 1, word).unwrap();
+        }
+    }
+
+    if program.symbols.is_empty() {
+        return output;
+    }
+
+    let name_width = program.symbols.iter().map(|symbol| symbol.name.len()).max().unwrap_or(0).max(4);
+    writeln!(output).unwrap();
+    writeln!(output, "{:name_width$}  kind      value", "name").unwrap();
+    for symbol in program.symbols.iter() {
+        let value = match symbol.kind {
+            SymbolKind::Label => format!("{:04X}", symbol.value),
+            SymbolKind::Constant if symbol.value < 0 => symbol.value.to_string(),
+            SymbolKind::Constant => format!("{} (0x{:X})", symbol.value, symbol.value),
+        };
+        writeln!(output, "{:name_width$}  {:8}  {}", symbol.name, symbol.kind.name(), value).unwrap();
+    }
+
+    output
+}
@@ -1,236 +1,617 @@
-mod instructions;
-
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::process::exit;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use anyhow::{anyhow, Context, Result};
 use colored::Colorize;
-use regex::Regex;
-use crate::instructions::Instruction;
-use crate::instructions::build_error;
-use crate::instructions::bomb::Bomb;
-use crate::instructions::helpers::matches;
-use crate::instructions::jumps::Jump;
-use crate::instructions::mem_manipulation::MemManipulation;
-use crate::instructions::reg_manipulation::RegManipulation;
-use crate::instructions::set_imms::SetImm;
-use crate::instructions::teleport::Teleport;
+use sopt_lang_assembler::{assemble_with, disassemble, Diagnostics, Format, Options, Program, Style};
+use sopt_lang_assembler::emulator::{self, Event, HaltReason};
+use sopt_lang_assembler::diagnostics::Severity;
+use sopt_lang_assembler::formatter::format_source;
+use sopt_lang_assembler::lexer::{number_value, tokenize, TokenKind};
+use sopt_lang_assembler::lints::{lint, Level, Lint, LintLevels};
+use sopt_lang_assembler::listing::listing;
+use sopt_lang_assembler::suggestions::did_you_mean;
+use sopt_lang_assembler::symbol_map::{to_json, to_sym};
+
+/// Instructions `run` executes before giving up on a program that does not end.
+const DEFAULT_MAX_STEPS: u64 = 1_000_000;
+
+const USAGE: &str = "Usage:
+    sopt-lang-assembler <command> [options] <input>
+
+Commands:
+    asm       assemble a program into a .tik, .bin or .hex file
+    disasm    turn a .tik file back into source
+    run       assemble a program and run it in the emulator
+    check     assemble a program and report problems without writing anything
+    fmt       format a program's source
+
+Use `-` as a file name to read from stdin or write to stdout.
+Run `sopt-lang-assembler <command> --help` to see the options of a command.";
+
+const ASM_HELP: &str = "Usage:
+    sopt-lang-assembler asm [options] <input.sop> [<output>]
+
+Assembles <input.sop>. Without an output file the result is written next to the input
+with the extension of the format, or to stdout when reading from stdin.
+
+Options:
+    -o, --output <file>         where to write the program
+    -f, --format <format>       tik, compact, bin or hex, taken from the output's extension by default
+    -l, --listing <file>        also write a listing of the program
+    -s, --symbols <file>        also write the labels and constants, as .sym or .json
+    -I, --include-path <dir>    look for included files in <dir>, can be repeated
+    -D, --define <name>[=<value>]
+                                define a constant before the first line, 1 by default, can be repeated
+        --fill <word>           word written into .org and .align gaps, 0x69000000 (NOP) by default
+        --message-format <format>
+                                human (default) or json, which prints every problem as a JSON object on stdout
+        --force                 overwrite files that already exist
+    -h, --help                  show this help";
+
+const DISASM_HELP: &str = "Usage:
+    sopt-lang-assembler disasm [options] <input.tik> [<output>]
+
+Turns the words of <input.tik> back into source. Without an output file the source is written
+next to the input with the .sop extension, or to stdout when reading from stdin.
+
+Options:
+    -o, --output <file>         where to write the source
+        --style <style>         infix (default) or mnemonic
+        --force                 overwrite a file that already exists
+    -h, --help                  show this help";
+
+const RUN_HELP: &str = "Usage:
+    sopt-lang-assembler run [options] <input.sop>
+
+Assembles <input.sop>, runs it in the emulator and prints why it stopped, the registers and
+every TELEPORT and BOMB. Exits with status 1 unless the program ran to its end.
+
+Options:
+        --max-steps <count>     stop after this many instructions, 1000000 by default
+    -I, --include-path <dir>    look for included files in <dir>, can be repeated
+    -D, --define <name>[=<value>]
+                                define a constant before the first line, 1 by default, can be repeated
+        --fill <word>           word written into .org and .align gaps, 0x69000000 (NOP) by default
+        --message-format <format>
+                                human (default) or json, which prints every problem as a JSON object on stdout
+    -h, --help                  show this help";
+
+const CHECK_HELP: &str = "Usage:
+    sopt-lang-assembler check [options] <input.sop>
 
-fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+Assembles <input.sop> and reports every problem without writing any file, along with warnings
+about code that assembles but is probably wrong. Exits with status 1 if there are errors.
 
-    let usage = "Usage:\n\t./sopt-lang <path to input.sop> <path to output.tik>".bright_green();
+Options:
+        --allow <lint>          do not report <lint>, can be repeated
+        --warn <lint>           report <lint> as a warning, which is the default, can be repeated
+        --deny <lint>           report <lint> as an error, can be repeated
+    -I, --include-path <dir>    look for included files in <dir>, can be repeated
+    -D, --define <name>[=<value>]
+                                define a constant before the first line, 1 by default, can be repeated
+        --message-format <format>
+                                human (default) or json, which prints every problem as a JSON object on stdout
+    -h, --help                  show this help
 
-    if args.len() != 3 {
-        println!("{}", usage);
-        exit(0);
+Lints, or `all` for every one of them:
+    jump-outside-program        a jump to an address before the program or past its end
+    unreachable-code            instructions nothing leads to, like after `if (reg0 == reg0) pc += 3`
+    never-taken                 `if (regX < regX)` or `if (regX != regX)`, which never jump
+    unwritten-register          a register that is read but never written, so it is always 0
+    memory-out-of-bounds        a LOAD or STORE of an address known to be past the end of memory
+    only-nops                   a program with no instruction besides NOP";
+
+const FMT_HELP: &str = "Usage:
+    sopt-lang-assembler fmt [options] <input.sop>
+
+Formats <input.sop> and prints the result to stdout, or writes it to the output file.
+
+Options:
+    -o, --output <file>         where to write the formatted source, the input itself with --force formats in place
+        --check                 write nothing and exit with status 1 if the input is not formatted
+        --force                 overwrite a file that already exists
+    -h, --help                  show this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Asm,
+    Disasm,
+    Run,
+    Check,
+    Fmt,
+}
+
+impl Command {
+    const ALL: [Command; 5] = [Command::Asm, Command::Disasm, Command::Run, Command::Check, Command::Fmt];
+
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "asm" => Some(Command::Asm),
+            "disasm" => Some(Command::Disasm),
+            "run" => Some(Command::Run),
+            "check" => Some(Command::Check),
+            "fmt" => Some(Command::Fmt),
+            _ => None,
+        }
     }
 
-    let sop_raw_file = args.get(1).context("can not find input file argument")?;
-    let tik_raw_file = args.get(2).context("can not find input file argument")?;
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Asm => "asm",
+            Command::Disasm => "disasm",
+            Command::Run => "run",
+            Command::Check => "check",
+            Command::Fmt => "fmt",
+        }
+    }
 
-    if !sop_raw_file.ends_with(".sop") {
-        return Err(anyhow!("input file must end with .sop\n:)"));
+    fn help(&self) -> &'static str {
+        match self {
+            Command::Asm => ASM_HELP,
+            Command::Disasm => DISASM_HELP,
+            Command::Run => RUN_HELP,
+            Command::Check => CHECK_HELP,
+            Command::Fmt => FMT_HELP,
+        }
     }
 
-    if !tik_raw_file.ends_with(".tik") {
-        return Err(anyhow!("output file must end with .tik\n:)"));
+    /// Whether the command takes `option`, named by its long form.
+    fn accepts(&self, option: &str) -> bool {
+        let options: &[&str] = match self {
+            Command::Asm => &["--output", "--format", "--listing", "--symbols", "--include-path", "--define", "--fill", "--message-format", "--force"],
+            Command::Disasm => &["--output", "--style", "--force"],
+            Command::Run => &["--max-steps", "--include-path", "--define", "--fill", "--message-format"],
+            Command::Check => &["--include-path", "--define", "--message-format", "--allow", "--warn", "--deny"],
+            Command::Fmt => &["--output", "--check", "--force"],
+        };
+        options.contains(&option)
     }
 
-    let mut sop_file = File::open(sop_raw_file).context("can not find input file")?;
+    /// Most files the command takes, the input and for some an output.
+    fn max_files(&self) -> usize {
+        match self {
+            Command::Asm | Command::Disasm => 2,
+            Command::Run | Command::Check | Command::Fmt => 1,
+        }
+    }
+}
 
-    let mut input = String::new();
-    sop_file.read_to_string(&mut input).context("cannot read input file to string")?;
-
-    if File::open(tik_raw_file).is_ok() {
-        return Err(anyhow!(format!("{} file already exists", tik_raw_file)));
-    }
-
-    let add_regexes = vec![
-        Regex::new(r"(.*)\+=(.*)\+(.*)").context("can not create regex for ADD1")?,
-        Regex::new(r"^ADD\((.*),(.*),(.*)\)").context("can not create regex for ADD2")?,
-        Regex::new(r"^ADD(.*),(.*),(.*)").context("can not create regex for ADD3")?,
-    ];
-    let sub_regexes = vec![
-        Regex::new(r"(.*)-=(.*)\+(.*)").context("can not create regex for SUB1")?,
-        Regex::new(r"^SUB\((.*),(.*),(.*)\)").context("can not create regex for SUB2")?,
-        Regex::new(r"^SUB(.*),(.*),(.*)").context("can not create regex for SUB3")?,
-    ];
-    let mul_regexes = vec![
-        Regex::new(r"(.*)\*=(.*)\+(.*)").context("can not create regex for MUL1")?,
-        Regex::new(r"^MUL\((.*),(.*),(.*)\)").context("can not create regex for MUL2")?,
-        Regex::new(r"^MUL(.*),(.*),(.*)").context("can not create regex for MUL3")?,
-    ];
-
-    let load_regexes = vec![
-        // Regex::new(r"(.*)=mem\[(.*)\+(.*)]").context("can not create regex for LOAD1")?, // TODO: Fix regexes?
-        Regex::new(r"^LOAD\((.*),(.*),(.*)\)").context("can not create regex for LOAD2")?,
-        Regex::new(r"^LOAD(.*),(.*),(.*)").context("can not create regex for LOAD3")?,
-    ];
-    let store_regexes = vec![
-        // Regex::new(r"mem\[(.*)\+(.*)]=(.*)").context("can not create regex for STORE1")?, // TODO: Fix regexes?
-        Regex::new(r"^STORE\((.*),(.*),(.*)\)").context("can not create regex for STORE2")?,
-        Regex::new(r"^STORE(.*),(.*),(.*)").context("can not create regex for STORE3")?,
-    ];
-
-    let mov_regexes = vec![
-        Regex::new(r"([^*+-]+)=(.*)\+([^=]+)").context("can not create regex for MOV1")?, // TODO: Is this correct?
-        Regex::new(r"^MOV\((.*),(.*),(.*)\)").context("can not create regex for MOV2")?,
-        Regex::new(r"^MOV(.*),(.*),(.*)").context("can not create regex for MOV3")?,
-    ];
-
-    let jump_regexes = vec![
-        Regex::new(r"^if\((.*)==(.*)\)pc\+=(.*)").context("can not create regex for JUMP1")?,
-        Regex::new(r"^JUMP\((.*),(.*),(.*)\)").context("can not create regex for JUMP2")?,
-        Regex::new(r"^JUMP(.*),(.*),(.*)").context("can not create regex for JUMP3")?,
-    ];
-    let rev_jump_regexes = vec![
-        Regex::new(r"^if\((.*)==(.*)\)pc-=(.*)").context("can not create regex for REVJUMP1")?,
-        Regex::new(r"^REVJUMP\((.*),(.*),(.*)\)").context("can not create regex for REVJUMP2")?,
-        Regex::new(r"^REVJUMP(.*),(.*),(.*)").context("can not create regex for REVJUMP3")?,
-    ];
-
-    let lt_jump_regexes = vec![
-        Regex::new(r"^if\((.*)<(.*)\)pc\+=(.*)").context("can not create regex for LTJUMP1")?,
-        Regex::new(r"^LTJUMP\((.*),(.*),(.*)\)").context("can not create regex for LTJUMP2")?,
-        Regex::new(r"^LTJUMP(.*),(.*),(.*)").context("can not create regex for LTJUMP3")?,
-    ];
-    let rev_lt_jump_regexes = vec![
-        Regex::new(r"^if\((.*)<(.*)\)pc-=(.*)").context("can not create regex for REVLTJUMP1")?,
-        Regex::new(r"^REVLTJUMP\((.*),(.*),(.*)\)").context("can not create regex for REVLTJUMP2")?,
-        Regex::new(r"^REVLTJUMP(.*),(.*),(.*)").context("can not create regex for REVLTJUMP3")?,
-    ];
-
-    let neq_jump_regexes = vec![
-        Regex::new(r"^if\((.*)!=(.*)\)pc\+=(.*)").context("can not create regex for NEQJUMP1")?,
-        Regex::new(r"^NEQJUMP\((.*),(.*),(.*)\)").context("can not create regex for NEQJUMP2")?,
-        Regex::new(r"^NEQJUMP(.*),(.*),(.*)").context("can not create regex for NEQJUMP3")?,
-    ];
-    let rev_neq_jump_regexes = vec![
-        Regex::new(r"^if\((.*)!=(.*)\)pc-=(.*)").context("can not create regex for REVNEQJUMP1")?,
-        Regex::new(r"^REVNEQJUMP\((.*),(.*),(.*)\)").context("can not create regex for REVNEQJUMP2")?,
-        Regex::new(r"^REVNEQJUMP(.*),(.*),(.*)").context("can not create regex for REVNEQJUMP3")?,
-    ];
-
-    let set_imm_low_regexes = vec![
-        Regex::new(r"(.*)\[low]=(.*)").context("can not create regex for SETIMMLOW1")?,
-        Regex::new(r"^SETIMMLOW\((.*),(.*)\)").context("can not create regex for SETIMMLOW2")?,
-        Regex::new(r"^SETIMMLOW(.*),(.*)").context("can not create regex for SETIMMLOW3")?
-    ];
-    let set_imm_high_regexes = vec![
-        Regex::new(r"(.*)\[high]=(.*)").context("can not create regex for SETIMMHIGH1")?,
-        Regex::new(r"^SETIMMHIGH\((.*),(.*)\)").context("can not create regex for SETIMMHIGH2")?,
-        Regex::new(r"^SETIMMHIGH(.*),(.*)").context("can not create regex for SETIMMHIGH3")?
-    ];
-
-    let teleport_regexes = vec![
-        Regex::new(r"^TELEPORT\((.*),(.*)\)").context("can not create regex for TELEPORT")?,
-        Regex::new(r"^TELEPORT(.*),(.*)").context("can not create regex for TELEPORT")?,
-    ];
-
-    let bomb_regexes = vec![
-        Regex::new(r"^BOMB\((.*)\)").context("can not create regex for BOMB2")?,
-        Regex::new(r"^BOMB(.*)").context("can not create regex for BOMB1")?
-    ];
-
-    let instructions: Vec<&str> = input.split('\n').collect();
-    let mut output = String::new();
-
-    for (index, raw_instruction_and_comment) in instructions.iter().enumerate() {
-        let instruction_and_comment = raw_instruction_and_comment.split_whitespace().collect::<String>();
-        if instruction_and_comment.is_empty() {
-            output.push('\n');
-            continue;
+/// A mistake in the command line, reported with a pointer to the help and exit status 2.
+#[derive(Debug)]
+struct UsageError {
+    message: String,
+    command: Option<Command>,
+}
+
+impl Display for UsageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.command {
+            Some(command) => write!(f, "{}\n\nRun `sopt-lang-assembler {} --help` to see its options.", self.message, command.name()),
+            None => write!(f, "{}\n\n{}", self.message, USAGE),
         }
+    }
+}
 
-        let instruction = if let Some((instruction, _)) = instruction_and_comment.split_once(';') {
-            instruction
-        } else {
-            &instruction_and_comment as &str
-        };
+impl std::error::Error for UsageError {}
 
-        let problem_line = format!("{}. {raw_instruction_and_comment}", index + 1);
+/// How problems in a program are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MessageFormat {
+    /// Coloured text with the source line, like rustc.
+    Human,
+    /// One JSON object per line on stdout, for editors and other tools.
+    Json,
+}
 
-        macro_rules! process_reg_instruction {
-            ($instruction:expr, $matched_regex:expr, $param:expr, $output:expr, $problem_line:expr) => {
-                match RegManipulation::parse($instruction, $matched_regex, $param) {
-                    Ok(instruction) => $output.push_str(&format!("{} ; {}\n", instruction, raw_instruction_and_comment)),
-                    Err(err) => return Err(anyhow!(build_error(err, $problem_line, &$param))),
-                }
-            };
+/// The command line after the command name.
+struct Arguments {
+    /// Input file, `-` for stdin.
+    input: String,
+    output: Option<String>,
+    format: Option<Format>,
+    style: Style,
+    message_format: MessageFormat,
+    listing: Option<String>,
+    symbols: Option<String>,
+    options: Options,
+    lints: LintLevels,
+    max_steps: u64,
+    check: bool,
+    force: bool,
+}
+
+fn main() -> ExitCode {
+    // Errors are the only coloured output, so colour follows whether they go to a terminal.
+    colored::control::set_override(io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none());
+    match cli(env::args().skip(1).collect()) {
+        Ok(status) => status,
+        Err(err) => {
+            eprintln!("{} {:#}", "error:".red().bold(), err);
+            ExitCode::from(if err.is::<UsageError>() { 2 } else { 1 })
         }
+    }
+}
 
-        macro_rules! process_jump_instruction {
-            ($instruction:expr, $matched_regex:expr, $param:expr, $output:expr, $problem_line:expr) => {
-                match Jump::parse($instruction, $matched_regex, $param) {
-                    Ok(jump) => $output.push_str(&format!("{} ; {}\n", jump, raw_instruction_and_comment)),
-                    Err(err) => return Err(anyhow!(build_error(err, $problem_line, &$param))),
-                }
-            };
+fn cli(args: Vec<String>) -> Result<ExitCode> {
+    let Some(first) = args.first() else {
+        return Err(UsageError { message: "missing command".to_owned(), command: None }.into());
+    };
+    if first == "--help" || first == "-h" || first == "help" {
+        println!("{}", USAGE);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Without a command, `sopt-lang-assembler program.sop output.tik` assembles like it always did.
+    let legacy = args.len() == 2 && (first == "-" || Path::new(first).is_file()) && !args[1].starts_with('-');
+    let (command, rest) = match Command::from_name(first) {
+        Some(command) => (command, &args[1..]),
+        None if legacy => (Command::Asm, &args[..]),
+        None => {
+            let mut message = format!("unknown command `{}`", first);
+            if let Some(help) = did_you_mean(first, Command::ALL.iter().map(Command::name)) {
+                message = format!("{}, {}", message, help);
+            }
+            return Err(UsageError { message, command: None }.into());
         }
+    };
+    if rest.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", command.help());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let arguments = parse_arguments(command, rest)?;
+    match command {
+        Command::Asm => assemble_command(&arguments),
+        Command::Disasm => disassemble_command(&arguments),
+        Command::Run => run_command(&arguments),
+        Command::Check => check_command(&arguments),
+        Command::Fmt => format_command(&arguments),
+    }
+}
+
+fn parse_arguments(command: Command, args: &[String]) -> Result<Arguments> {
+    let usage = |message: String| -> anyhow::Error { UsageError { message, command: Some(command) }.into() };
+
+    let mut options = Options::default();
+    let mut files = Vec::new();
+    let mut output = None;
+    let mut format = None;
+    let mut style = Style::Infix;
+    let mut message_format = MessageFormat::Human;
+    let mut listing = None;
+    let mut symbols = None;
+    let mut lints = LintLevels::new();
+    let mut max_steps = DEFAULT_MAX_STEPS;
+    let mut check = false;
+    let mut force = false;
 
-        if instruction.is_empty() {
-            output.push_str(&format!("{}\n", raw_instruction_and_comment));
-        } else if instruction == "NOP" {
-            output.push_str(&format!("69 00 00 00 ; {raw_instruction_and_comment}\n"));
-        } else if let Some(matched_regex) = matches(instruction, &add_regexes) {
-            process_reg_instruction!(instruction, matched_regex, 1, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &sub_regexes) {
-            process_reg_instruction!(instruction, matched_regex, 2, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &mul_regexes) {
-            process_reg_instruction!(instruction, matched_regex, 3, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &mov_regexes) {
-            process_reg_instruction!(instruction, matched_regex, 7, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &load_regexes) {
-            match MemManipulation::parse(instruction, matched_regex, 5, true) {
-                Ok(load) => output.push_str(&format!("{} ; {}\n", load, raw_instruction_and_comment)),
-                Err(err) => return Err(anyhow!(build_error(err, problem_line, &5))),
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut inline = None;
+        let option = match arg.as_str() {
+            "-o" => "--output",
+            "-f" => "--format",
+            "-l" => "--listing",
+            "-s" => "--symbols",
+            "-I" => "--include-path",
+            "-D" => "--define",
+            // `--name=value` is the same as `--name value`.
+            option if option.starts_with("--") && option.contains('=') => {
+                let (option, value) = option.split_once('=').expect("`=` was found");
+                inline = Some(value.to_owned());
+                option
             }
-        } else if let Some(matched_regex) = matches(instruction, &store_regexes) {
-            match MemManipulation::parse(instruction, matched_regex, 6, false) {
-                Ok(store) => output.push_str(&format!("{} ; {}\n", store, raw_instruction_and_comment)),
-                Err(err) => return Err(anyhow!(build_error(err, problem_line, &6))),
+            option if option.starts_with('-') && option != "-" => option,
+            _ => {
+                files.push(arg.clone());
+                continue;
             }
-        } else if let Some(matched_regex) = matches(instruction, &jump_regexes) {
-            process_jump_instruction!(instruction, matched_regex, 10, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &rev_jump_regexes) {
-            process_jump_instruction!(instruction, matched_regex, 11, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &lt_jump_regexes) {
-            process_jump_instruction!(instruction, matched_regex, 12, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &rev_lt_jump_regexes) {
-            process_jump_instruction!(instruction, matched_regex, 13, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &neq_jump_regexes) {
-            process_jump_instruction!(instruction, matched_regex, 14, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &rev_neq_jump_regexes) {
-            process_jump_instruction!(instruction, matched_regex, 15, output, problem_line);
-        } else if let Some(matched_regex) = matches(instruction, &set_imm_low_regexes) {
-            match SetImm::parse(instruction, matched_regex, 20) {
-                Ok(low) => output.push_str(&format!("{} ; {}\n", low, raw_instruction_and_comment)),
-                Err(err) => return Err(anyhow!(build_error(err, problem_line, &20))),
+        };
+        if !command.accepts(option) {
+            return Err(usage(format!("`{}` does not take `{}`", command.name(), arg)));
+        }
+
+        let mut value = || inline.take().or_else(|| args.next().cloned()).ok_or_else(|| usage(format!("`{}` needs a value", arg)));
+        match option {
+            "--output" => output = Some(value()?),
+            "--format" => {
+                let name = value()?;
+                format = Some(Format::from_name(&name).ok_or_else(|| usage(format!("unknown format `{}`, expected tik, compact, bin or hex", name)))?);
+            }
+            "--style" => {
+                style = match value()?.as_str() {
+                    "infix" => Style::Infix,
+                    "mnemonic" => Style::Mnemonic,
+                    name => return Err(usage(format!("unknown style `{}`, expected infix or mnemonic", name))),
+                }
+            }
+            "--message-format" => {
+                message_format = match value()?.as_str() {
+                    "human" => MessageFormat::Human,
+                    "json" => MessageFormat::Json,
+                    name => return Err(usage(format!("unknown message format `{}`, expected human or json", name))),
+                }
             }
-        } else if let Some(matched_regex) = matches(instruction, &set_imm_high_regexes) {
-            match SetImm::parse(instruction, matched_regex, 21) {
-                Ok(high) => output.push_str(&format!("{} ; {}\n", high, raw_instruction_and_comment)),
-                Err(err) => return Err(anyhow!(build_error(err, problem_line, &21))),
+            "--listing" => listing = Some(value()?),
+            "--symbols" => {
+                let file = value()?;
+                if !file.ends_with(".sym") && !file.ends_with(".json") && file != "-" {
+                    return Err(usage(format!("symbols file `{}` must end with .sym or .json", file)));
+                }
+                symbols = Some(file);
+            }
+            "--include-path" => options.include_paths.push(PathBuf::from(value()?)),
+            "--define" => {
+                let (name, number) = parse_define(&value()?).map_err(|err| usage(err.to_string()))?;
+                options.defines.retain(|(defined, _)| *defined != name);
+                options.defines.push((name, number));
             }
-        } else if let Some(matched_regex) = matches(instruction, &bomb_regexes) {
-            match Bomb::parse(instruction, matched_regex, 50) {
-                Ok(bomb) => output.push_str(&format!("{} ; {}\n", bomb, raw_instruction_and_comment)),
-                Err(err) => return Err(anyhow!(build_error(err, problem_line, &50))),
+            "--fill" => {
+                let word = value()?;
+                options.fill = number_value(&word)
+                    .and_then(|word| u32::try_from(word).ok())
+                    .ok_or_else(|| usage(format!("`{}` is not a 32-bit word like 0x69000000", word)))?
+                    .to_be_bytes();
             }
-        } else if let Some(matched_regex) = matches(instruction, &teleport_regexes) {
-            match Teleport::parse(instruction, matched_regex, 42) {
-                Ok(teleport) => output.push_str(&format!("{} ; {}\n", teleport, raw_instruction_and_comment)),
-                Err(err) => return Err(anyhow!(build_error(err, problem_line, &42))),
+            "--max-steps" => {
+                let count = value()?;
+                max_steps = number_value(&count)
+                    .and_then(|count| u64::try_from(count).ok())
+                    .ok_or_else(|| usage(format!("`{}` is not a number of steps", count)))?;
+            }
+            "--allow" | "--warn" | "--deny" => {
+                let level = match option {
+                    "--allow" => Level::Allow,
+                    "--warn" => Level::Warn,
+                    _ => Level::Deny,
+                };
+                match value()?.as_str() {
+                    "all" => lints.set_all(level),
+                    name => lints.set(Lint::from_name(name).ok_or_else(|| usage(format!("unknown lint `{}`", name)))?, level),
+                }
             }
-        } else {
-            let problem = format!("in your program on line:\n\n{}\n\nproblem: {}", problem_line, "unknown instruction".red());
-            return Err(anyhow!(problem));
+            "--check" => check = true,
+            "--force" => force = true,
+            _ => unreachable!("every accepted option is handled"),
+        }
+        if inline.is_some() {
+            return Err(usage(format!("`{}` does not take a value", option)));
         }
     }
 
-    let mut tik_file = File::create(tik_raw_file).expect("can not create output file");
-    tik_file.write_all(output.as_bytes()).context("failed to write program into .tik file")?;
+    if let Some(file) = files.get(command.max_files()) {
+        return Err(usage(format!("unexpected file `{}`", file)));
+    }
+    let mut files = files.into_iter();
+    let input = files.next().ok_or_else(|| usage("missing input file".to_owned()))?;
+    if let Some(file) = files.next() {
+        if output.is_some() {
+            return Err(usage(format!("`{}` and `--output` both name the output", file)));
+        }
+        output = Some(file);
+    }
+
+    if input != "-" {
+        options.path = Some(PathBuf::from(&input));
+    }
+
+    Ok(Arguments { input, output, format, style, message_format, listing, symbols, options, lints, max_steps, check, force })
+}
+
+/// Splits `NAME=VALUE` from `--define`, where a missing value means 1.
+fn parse_define(define: &str) -> Result<(String, i64)> {
+    let (name, value) = match define.split_once('=') {
+        Some((name, value)) => (name, number_value(value.trim()).ok_or_else(|| anyhow!("`{}` is not a valid value for {}", value, name))?),
+        None => (define, 1),
+    };
+    match tokenize(name, 1, 0).as_slice() {
+        [token] if matches!(token.kind, TokenKind::Identifier(_)) => Ok((name.to_owned(), value)),
+        _ => Err(anyhow!("`{}` is not a valid constant name", name)),
+    }
+}
+
+fn assemble_command(arguments: &Arguments) -> Result<ExitCode> {
+    let format = arguments.format.or_else(|| arguments.output.as_deref().and_then(format_of)).unwrap_or_default();
+    let output = output_path(arguments, format.extension());
+
+    // Every output is checked first, so nothing is written if one of them can not be.
+    for file in [Some(&output), arguments.listing.as_ref(), arguments.symbols.as_ref()].into_iter().flatten() {
+        check_writable(file, arguments.force)?;
+    }
+
+    let program = assemble_input(arguments, Some(&output))?;
+    if let Some(file) = &arguments.listing {
+        write_output(file, listing(&program).as_bytes())?;
+    }
+    if let Some(file) = &arguments.symbols {
+        let map = if file.ends_with(".json") { to_json(&program.symbols) } else { to_sym(&program.symbols) };
+        write_output(file, map.as_bytes())?;
+    }
+    write_output(&output, &format.write(&program))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn disassemble_command(arguments: &Arguments) -> Result<ExitCode> {
+    let output = output_path(arguments, ".sop");
+    check_writable(&output, arguments.force)?;
+
+    let source = disassemble(&read_input(&arguments.input)?, arguments.style)?;
+    write_output(&output, source.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_command(arguments: &Arguments) -> Result<ExitCode> {
+    let program = assemble_input(arguments, None)?;
+    let execution = emulator::run(&program, arguments.max_steps);
+    let machine = &execution.machine;
+
+    println!("stopped: {} after {} steps", execution.halt, machine.steps);
+    println!("pc    {:#010X}", machine.pc);
+    for (number, value) in machine.registers.iter().enumerate() {
+        println!("reg{}  {:#010X}  {}", number, value, *value as i32);
+    }
+    for event in &machine.events {
+        match event {
+            Event::Teleport { pc, imm1, imm2 } => println!("TELEPORT {}, {} at pc {}", imm1, imm2, pc),
+            Event::Bomb { pc, imm1 } => println!("BOMB {} at pc {}", imm1, pc),
+        }
+    }
+
+    Ok(if execution.halt == HaltReason::EndOfProgram { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+fn check_command(arguments: &Arguments) -> Result<ExitCode> {
+    let program = assemble_input(arguments, None)?;
+    let mut diagnostics = lint(&program, &arguments.lints);
+    if diagnostics.is_empty() {
+        summary(arguments, &format!("{}: no problems, {}", display_name(&arguments.input), plural(program.words().count(), "word")));
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(path) = &arguments.options.path {
+        diagnostics.set_source_path(&path.display().to_string());
+    }
+    report(arguments, &diagnostics);
+    if diagnostics.count(Severity::Error) > 0 {
+        return Err(anyhow!("found {}", problems(&diagnostics)));
+    }
+    summary(arguments, &format!("{}: {}, {}", display_name(&arguments.input), problems(&diagnostics), plural(program.words().count(), "word")));
+    Ok(ExitCode::SUCCESS)
+}
+
+fn format_command(arguments: &Arguments) -> Result<ExitCode> {
+    let source = read_input(&arguments.input)?;
+    let formatted = format_source(&source);
+
+    if arguments.check {
+        if formatted == source {
+            return Ok(ExitCode::SUCCESS);
+        }
+        eprintln!("{} is not formatted", display_name(&arguments.input));
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let output = arguments.output.as_deref().unwrap_or("-");
+    check_writable(output, arguments.force)?;
+    write_output(output, formatted.as_bytes())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Assembles the input, printing the diagnostics if there are problems. `output` is the file that is then not written.
+fn assemble_input(arguments: &Arguments, output: Option<&str>) -> Result<Program> {
+    let source = read_input(&arguments.input)?;
+    assemble_with(&source, &arguments.options).map_err(|diagnostics| {
+        report(arguments, &diagnostics);
+        match output {
+            Some(output) if output != "-" => anyhow!("found {}, {} was not written", problems(&diagnostics), output),
+            _ => anyhow!("found {}", problems(&diagnostics)),
+        }
+    })
+}
+
+fn report(arguments: &Arguments, diagnostics: &Diagnostics) {
+    match arguments.message_format {
+        MessageFormat::Human => eprintln!("{}\n", diagnostics),
+        MessageFormat::Json => print!("{}", diagnostics.to_json()),
+    }
+}
+
+/// Prints the closing line of `check`, on stderr with JSON diagnostics so stdout only holds JSON Lines.
+fn summary(arguments: &Arguments, text: &str) {
+    match arguments.message_format {
+        MessageFormat::Human => println!("{}", text),
+        MessageFormat::Json => eprintln!("{}", text),
+    }
+}
+
+/// Counts of errors and warnings, like "2 errors and 1 warning".
+fn problems(diagnostics: &Diagnostics) -> String {
+    let count = |severity: Severity, name: &str| {
+        let count = diagnostics.count(severity);
+        (count > 0).then(|| plural(count, name))
+    };
+    [count(Severity::Error, "error"), count(Severity::Warning, "warning")].into_iter().flatten().collect::<Vec<_>>().join(" and ")
+}
+
+/// `count` followed by `name`, with an `s` unless there is one, like "1 word" or "3 words".
+fn plural(count: usize, name: &str) -> String {
+    format!("{} {}{}", count, name, if count == 1 { "" } else { "s" })
+}
+
+/// The output file from the command line, or else the input with `extension`, or stdout for stdin.
+fn output_path(arguments: &Arguments, extension: &str) -> String {
+    match (&arguments.output, arguments.input.as_str()) {
+        (Some(output), _) => output.clone(),
+        (None, "-") => "-".to_owned(),
+        (None, input) => Path::new(input).with_extension(&extension[1..]).display().to_string(),
+    }
+}
+
+/// The format an output file's extension asks for.
+fn format_of(file: &str) -> Option<Format> {
+    match Path::new(file).extension()?.to_str()? {
+        "tik" => Some(Format::Tik),
+        "bin" => Some(Format::Binary),
+        "hex" => Some(Format::IntelHex),
+        _ => None,
+    }
+}
+
+fn display_name(file: &str) -> &str {
+    if file == "-" { "stdin" } else { file }
+}
+
+fn read_input(file: &str) -> Result<String> {
+    let mut input = String::new();
+    if file == "-" {
+        io::stdin().read_to_string(&mut input).context("can not read stdin")?;
+    } else {
+        File::open(file)
+            .and_then(|mut file| file.read_to_string(&mut input))
+            .with_context(|| format!("can not read {}", file))?;
+    }
+    Ok(input)
+}
+
+fn check_writable(file: &str, force: bool) -> Result<()> {
+    if file != "-" && !force && Path::new(file).exists() {
+        return Err(anyhow!("{} already exists, use --force to overwrite it", file));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+fn write_output(file: &str, bytes: &[u8]) -> Result<()> {
+    if file == "-" {
+        let mut stdout = io::stdout().lock();
+        return stdout.write_all(bytes).and_then(|_| stdout.flush()).context("can not write to stdout");
+    }
+    fs::write(file, bytes).with_context(|| format!("can not write {}", file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lints(args: &[&str]) -> Result<LintLevels> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Ok(parse_arguments(Command::Check, &args)?.lints)
+    }
+
+    #[test]
+    fn lint_levels() {
+        let levels = lints(&["--deny", "unwritten-register", "in.sop"]).unwrap();
+        assert_eq!(levels.level(Lint::UnwrittenRegister), Level::Deny);
+        assert_eq!(levels.level(Lint::OnlyNops), Level::Warn);
+
+        // Later options win, so `all` can be narrowed down.
+        let levels = lints(&["--deny", "all", "--allow", "only-nops", "in.sop"]).unwrap();
+        assert_eq!(levels.level(Lint::UnwrittenRegister), Level::Deny);
+        assert_eq!(levels.level(Lint::OnlyNops), Level::Allow);
+
+        assert!(lints(&["--deny", "everything", "in.sop"]).is_err());
+    }
+}
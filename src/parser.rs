@@ -0,0 +1,999 @@
+use crate::expression::{BinaryOperator, Expression, UnaryOperator};
+use crate::instructions::{Mnemonic, ParseError, Slot, SpannedError};
+use crate::lexer::{tokenize, Span, Token, TokenKind};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OperandKind {
+    Register(u8),
+    /// Literal, constant or label name, or an expression combining them.
+    Expression(Expression),
+    /// `@name` jump target, resolved to a pc-relative distance.
+    Label(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operand {
+    pub kind: OperandKind,
+    /// The operand exactly as written in the source.
+    pub text: String,
+    pub span: Span,
+}
+
+/// An instruction with its operands in mnemonic order, whichever syntax it was written in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstructionNode {
+    pub mnemonic: Mnemonic,
+    /// Span of the mnemonic, or of the whole instruction for the infix syntax.
+    pub mnemonic_span: Span,
+    /// One operand per [`Mnemonic::slots`] entry.
+    pub operands: Vec<Operand>,
+}
+
+/// `.equ NAME, value` or `const NAME = value`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstantNode {
+    pub name: String,
+    pub name_span: Span,
+    pub value: Operand,
+}
+
+/// `LI reg1, value` or `reg1 = value`, a pseudo-instruction loading a full 32-bit value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadImmediateNode {
+    pub reg1: Operand,
+    pub value: Operand,
+}
+
+/// A directive that puts data instead of instructions into the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DataNode {
+    /// `.word value, value, ...`, one 32-bit word per value.
+    Words(Vec<Operand>),
+    /// `.bytes byte byte ...`, separated by spaces or commas and packed four to a word.
+    Bytes(Vec<Operand>),
+    /// `.ascii "text"`, packed four bytes to a word.
+    Ascii(String),
+    /// `.zero count`, that many zero words.
+    Zero(Operand),
+}
+
+/// A directive that moves the address of the following words, filling the gap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutNode {
+    /// `.org address`
+    Org(Operand),
+    /// `.align count`, up to the next multiple of `count` words.
+    Align(Operand),
+}
+
+/// A directive that decides which of the following lines are assembled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionalNode {
+    /// `.if condition`, true unless the condition is zero.
+    If(Operand),
+    /// `.ifdef NAME`, or `.ifndef NAME` if `defined` is false.
+    IfDefined { name: String, defined: bool },
+    Elif(Operand),
+    Else,
+    Endif,
+}
+
+impl ConditionalNode {
+    /// The directive as written, like `.ifdef`.
+    pub fn directive(&self) -> &'static str {
+        match self {
+            ConditionalNode::If(_) => ".if",
+            ConditionalNode::IfDefined { defined: true, .. } => ".ifdef",
+            ConditionalNode::IfDefined { defined: false, .. } => ".ifndef",
+            ConditionalNode::Elif(_) => ".elif",
+            ConditionalNode::Else => ".else",
+            ConditionalNode::Endif => ".endif",
+        }
+    }
+}
+
+/// The first line of a block that is repeated up to its `.endr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepeatNode {
+    /// `.rep count`
+    Rep(Operand),
+    /// `.for name = start, end`, once for every value from `start` up to but not including `end`.
+    For { variable: String, start: Operand, end: Operand },
+}
+
+impl RepeatNode {
+    /// The directive as written, `.rep` or `.for`.
+    pub fn directive(&self) -> &'static str {
+        match self {
+            RepeatNode::Rep(_) => ".rep",
+            RepeatNode::For { .. } => ".for",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeNode {
+    pub path: String,
+    /// Span of the quoted path.
+    pub span: Span,
+}
+
+/// `.macro name param, param, ...`, the first line of a macro definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroNode {
+    pub name: String,
+    pub name_span: Span,
+    pub parameters: Vec<Parameter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameter {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A macro argument, kept as source text to be substituted into the macro body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Argument {
+    pub text: String,
+    pub span: Span,
+    /// Whether the argument is more than a single token, like `BUF + 4`.
+    pub compound: bool,
+}
+
+/// `name arg, arg, ...` where `name` is not an instruction, expected to invoke a macro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroCallNode {
+    pub name: String,
+    pub name_span: Span,
+    pub arguments: Vec<Argument>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatementKind {
+    /// Blank or comment-only line.
+    Empty,
+    Instruction(InstructionNode),
+    LoadImmediate(LoadImmediateNode),
+    Constant(ConstantNode),
+    MacroDefinition(MacroNode),
+    /// `.endm`, the end of a macro definition.
+    MacroEnd,
+    MacroCall(MacroCallNode),
+    /// `.include "path"`.
+    Include(IncludeNode),
+    Data(DataNode),
+    Layout(LayoutNode),
+    Conditional(ConditionalNode),
+    Repeat(RepeatNode),
+    /// `.endr`, the end of a repeated block.
+    RepeatEnd,
+}
+
+/// `name:` at the start of a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub label: Option<Label>,
+    pub kind: StatementKind,
+    /// Span of the statement without its trailing comment.
+    pub span: Span,
+}
+
+/// Parses one source line into a [`Statement`].
+pub fn parse_line(text: &str, line: usize, offset: usize) -> Result<Statement, SpannedError> {
+    let tokens: Vec<Token> = tokenize(text, line, offset)
+        .into_iter()
+        .filter(|token| !matches!(token.kind, TokenKind::Comment(_)))
+        .collect();
+
+    let span = match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => first.span.to(last.span),
+        _ => Span { start: offset, end: offset, line, column: 1 },
+    };
+
+    let mut parser = Parser { text, offset, tokens, position: 0, end: span.after() };
+    let label = parser.label();
+    let kind = parser.statement()?;
+    Ok(Statement { label, kind, span })
+}
+
+struct Parser<'a> {
+    /// The line being parsed, `offset` bytes into the whole source.
+    text: &'a str,
+    offset: usize,
+    tokens: Vec<Token>,
+    position: usize,
+    /// Empty span right after the last token, used for errors at the end of the line.
+    end: Span,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_kind(&self) -> Option<&TokenKind> {
+        self.peek().map(|token| &token.kind)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        if token.is_some() {
+            self.position += 1;
+        }
+        token
+    }
+
+    fn at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek_kind() == Some(kind) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Error for the current token, or for the end of the line if there is none.
+    fn unexpected(&self, expected: &'static str) -> SpannedError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken(token.text.clone(), expected).at(token.span),
+            None => ParseError::UnexpectedToken("end of line".to_owned(), expected).at(self.end),
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind, expected: &'static str) -> Result<Span, SpannedError> {
+        match self.peek() {
+            Some(token) if &token.kind == kind => {
+                let span = token.span;
+                self.position += 1;
+                Ok(span)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), SpannedError> {
+        if self.at_end() { Ok(()) } else { Err(self.unexpected("end of line")) }
+    }
+
+    /// Source text covered by `span`.
+    fn source(&self, span: Span) -> String {
+        self.text[span.start - self.offset..span.end - self.offset].to_owned()
+    }
+
+    fn label(&mut self) -> Option<Label> {
+        let (Some(name), Some(colon)) = (self.tokens.get(self.position), self.tokens.get(self.position + 1)) else { return None };
+        match (&name.kind, &colon.kind) {
+            (TokenKind::Identifier(identifier), TokenKind::Colon) => {
+                let label = Label { name: identifier.clone(), span: name.span };
+                self.position += 2;
+                Some(label)
+            }
+            _ => None,
+        }
+    }
+
+    fn statement(&mut self) -> Result<StatementKind, SpannedError> {
+        let Some(first) = self.peek().cloned() else { return Ok(StatementKind::Empty) };
+
+        let node = match &first.kind {
+            TokenKind::Directive(directive) if directive == ".equ" => return self.constant(TokenKind::Comma, "`,`"),
+            TokenKind::Directive(directive) if directive == ".macro" => return self.macro_definition(),
+            TokenKind::Directive(directive) if directive == ".include" => return self.include(),
+            TokenKind::Directive(directive) if directive == ".org" || directive == ".align" => {
+                let org = directive == ".org";
+                self.next();
+                let operand = self.operand(Slot::Imm1)?;
+                self.expect_end()?;
+                return Ok(StatementKind::Layout(if org { LayoutNode::Org(operand) } else { LayoutNode::Align(operand) }));
+            }
+            TokenKind::Directive(directive) if matches!(directive.as_str(), ".if" | ".ifdef" | ".ifndef" | ".elif" | ".else" | ".endif") => {
+                let directive = directive.clone();
+                return self.conditional(&directive);
+            }
+            TokenKind::Directive(directive) if matches!(directive.as_str(), ".word" | ".bytes" | ".ascii" | ".zero") => {
+                let directive = directive.clone();
+                return self.data(&directive);
+            }
+            TokenKind::Directive(directive) if directive == ".endm" => {
+                self.next();
+                self.expect_end()?;
+                return Ok(StatementKind::MacroEnd);
+            }
+            TokenKind::Directive(directive) if directive == ".rep" => {
+                self.next();
+                let count = self.operand(Slot::Imm1)?;
+                self.expect_end()?;
+                return Ok(StatementKind::Repeat(RepeatNode::Rep(count)));
+            }
+            TokenKind::Directive(directive) if directive == ".for" => return self.for_loop(),
+            TokenKind::Directive(directive) if directive == ".endr" => {
+                self.next();
+                self.expect_end()?;
+                return Ok(StatementKind::RepeatEnd);
+            }
+            TokenKind::Directive(directive) => return Err(ParseError::UnknownDirective(directive.clone()).at(first.span)),
+            TokenKind::Identifier(name) if name == "const" && !self.infix_follows() => return self.constant(TokenKind::Equal, "`=`"),
+            TokenKind::Identifier(name) if name == "if" => self.conditional_jump()?,
+            TokenKind::Identifier(name) if name == "LI" && !self.infix_follows() => return self.load_immediate(),
+            TokenKind::Identifier(name) if name == "mem" && self.memory_follows(self.position) => self.store(first.span)?,
+            TokenKind::Identifier(name) => match Mnemonic::from_name(name) {
+                Some(mnemonic) => {
+                    self.next();
+                    self.mnemonic_form(mnemonic, first.span)?
+                }
+                None if self.infix_follows() => return self.infix(first.span),
+                None => return self.macro_call(),
+            },
+            TokenKind::Register(_) if self.infix_follows() => return self.infix(first.span),
+            _ => return Err(ParseError::UnknownInstruction.at(first.span)),
+        };
+
+        Ok(StatementKind::Instruction(node))
+    }
+
+    /// Rest of `.equ NAME, value` or `const NAME = value` after the keyword.
+    fn constant(&mut self, separator: TokenKind, expected: &'static str) -> Result<StatementKind, SpannedError> {
+        self.next();
+        let (name, name_span) = match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Identifier(name), span, .. }) => (name, span),
+            _ => return Err(self.unexpected("constant name")),
+        };
+        self.next();
+        self.expect(&separator, expected)?;
+        let value = self.operand(Slot::Imm1).map_err(|err| ParseError::MissingConstantValue(name.clone()).at(err.span))?;
+        self.expect_end()?;
+
+        Ok(StatementKind::Constant(ConstantNode { name, name_span, value }))
+    }
+
+    /// Rest of a `.word`, `.bytes`, `.ascii` or `.zero` line after the directive.
+    fn data(&mut self, directive: &str) -> Result<StatementKind, SpannedError> {
+        self.next();
+        let node = match directive {
+            ".word" => {
+                let mut values = vec![self.operand(Slot::Imm1)?];
+                while self.eat(&TokenKind::Comma) {
+                    values.push(self.operand(Slot::Imm1)?);
+                }
+                DataNode::Words(values)
+            }
+            ".bytes" => {
+                // Without commas `1 -2` would read as a subtraction, so every byte is a single term.
+                let mut bytes = Vec::new();
+                loop {
+                    let expression = self.unary()?;
+                    let span = expression.span();
+                    bytes.push(Operand { kind: OperandKind::Expression(expression), text: self.source(span), span });
+                    self.eat(&TokenKind::Comma);
+                    if self.at_end() {
+                        break;
+                    }
+                }
+                DataNode::Bytes(bytes)
+            }
+            ".ascii" => match self.peek_kind().cloned() {
+                Some(TokenKind::String(text)) => {
+                    self.next();
+                    DataNode::Ascii(text)
+                }
+                _ => return Err(self.unexpected("text in quotes")),
+            },
+            _ => DataNode::Zero(self.operand(Slot::Imm1)?),
+        };
+        self.expect_end()?;
+
+        Ok(StatementKind::Data(node))
+    }
+
+    /// Rest of a conditional directive after an optional label.
+    fn conditional(&mut self, directive: &str) -> Result<StatementKind, SpannedError> {
+        if let Some(label) = self.tokens.first().filter(|_| self.position > 0) {
+            return Err(ParseError::ConditionalLabel(label.text.clone()).at(label.span));
+        }
+        self.next();
+        let node = match directive {
+            ".if" => ConditionalNode::If(self.operand(Slot::Imm1)?),
+            ".elif" => ConditionalNode::Elif(self.operand(Slot::Imm1)?),
+            ".ifdef" | ".ifndef" => {
+                let name = match self.peek_kind() {
+                    Some(TokenKind::Identifier(name)) => name.clone(),
+                    _ => return Err(self.unexpected("symbol name")),
+                };
+                self.next();
+                ConditionalNode::IfDefined { name, defined: directive == ".ifdef" }
+            }
+            ".else" => ConditionalNode::Else,
+            _ => ConditionalNode::Endif,
+        };
+        self.expect_end()?;
+
+        Ok(StatementKind::Conditional(node))
+    }
+
+    /// Rest of `.for name = start, end` after the directive.
+    fn for_loop(&mut self) -> Result<StatementKind, SpannedError> {
+        self.next();
+        let variable = match self.peek_kind() {
+            Some(TokenKind::Identifier(name)) => name.clone(),
+            _ => return Err(self.unexpected("counter name")),
+        };
+        self.next();
+        self.expect(&TokenKind::Equal, "`=`")?;
+        let start = self.operand(Slot::Imm1)?;
+        self.expect(&TokenKind::Comma, "`,`")?;
+        let end = self.operand(Slot::Imm1)?;
+        self.expect_end()?;
+
+        Ok(StatementKind::Repeat(RepeatNode::For { variable, start, end }))
+    }
+
+    /// Rest of `.include "path"` after the directive.
+    fn include(&mut self) -> Result<StatementKind, SpannedError> {
+        self.next();
+        let (path, span) = match self.peek().cloned() {
+            Some(Token { kind: TokenKind::String(path), span, .. }) => (path, span),
+            _ => return Err(self.unexpected("file name in quotes")),
+        };
+        self.next();
+        self.expect_end()?;
+
+        Ok(StatementKind::Include(IncludeNode { path, span }))
+    }
+
+    /// Rest of `.macro name param, param, ...` after the directive.
+    fn macro_definition(&mut self) -> Result<StatementKind, SpannedError> {
+        self.next();
+        let (name, name_span) = match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Identifier(name), span, .. }) => (name, span),
+            _ => return Err(self.unexpected("macro name")),
+        };
+        self.next();
+
+        let mut parameters = Vec::new();
+        while !self.at_end() {
+            if !parameters.is_empty() {
+                self.expect(&TokenKind::Comma, "`,`")?;
+            }
+            match self.peek().cloned() {
+                Some(Token { kind: TokenKind::Identifier(name), span, .. }) => parameters.push(Parameter { name, span }),
+                _ => return Err(self.unexpected("parameter name")),
+            }
+            self.next();
+        }
+
+        Ok(StatementKind::MacroDefinition(MacroNode { name, name_span, parameters }))
+    }
+
+    /// `name arg, arg, ...`, where every argument runs up to the next `,` outside of brackets.
+    fn macro_call(&mut self) -> Result<StatementKind, SpannedError> {
+        let name = self.next().expect("macro name was peeked");
+        let mut arguments = Vec::new();
+
+        while !self.at_end() {
+            if !arguments.is_empty() {
+                self.expect(&TokenKind::Comma, "`,`")?;
+            }
+
+            let first = self.position;
+            let mut depth = 0usize;
+            while let Some(kind) = self.peek_kind() {
+                match kind {
+                    TokenKind::Comma if depth == 0 => break,
+                    TokenKind::LeftParen | TokenKind::LeftBracket => depth += 1,
+                    TokenKind::RightParen | TokenKind::RightBracket => depth = depth.saturating_sub(1),
+                    _ => {}
+                }
+                self.position += 1;
+            }
+            if self.position == first {
+                return Err(self.unexpected("macro argument"));
+            }
+
+            let span = self.tokens[first].span.to(self.tokens[self.position - 1].span);
+            arguments.push(Argument { text: self.source(span), span, compound: self.position - first > 1 });
+        }
+
+        Ok(StatementKind::MacroCall(MacroCallNode { name: name.text, name_span: name.span, arguments }))
+    }
+
+    /// Whether the first token is followed by an infix assignment operator or `[`.
+    fn infix_follows(&self) -> bool {
+        matches!(
+            self.tokens.get(self.position + 1).map(|token| &token.kind),
+            Some(TokenKind::PlusEqual | TokenKind::MinusEqual | TokenKind::StarEqual | TokenKind::Equal | TokenKind::LeftBracket)
+        )
+    }
+
+    /// Whether the token at `position` is `mem` followed by `[`.
+    fn memory_follows(&self, position: usize) -> bool {
+        matches!(self.tokens.get(position).map(|token| &token.kind), Some(TokenKind::Identifier(name)) if name == "mem")
+            && self.tokens.get(position + 1).map(|token| &token.kind) == Some(&TokenKind::LeftBracket)
+    }
+
+    /// Parses the operand for `slot`, reporting it as missing if there is none.
+    ///
+    /// Register slots take a single token, immediate slots an `@label` or an expression.
+    fn operand(&mut self, slot: Slot) -> Result<Operand, SpannedError> {
+        let missing = |parser: &Self| slot.missing().at(parser.peek().map_or(parser.end, |token| token.span));
+
+        if matches!(slot, Slot::Reg1 | Slot::Reg2) {
+            let kind = match self.peek_kind() {
+                Some(TokenKind::Register(number)) => OperandKind::Register(*number),
+                Some(TokenKind::Identifier(_) | TokenKind::Number(_)) => {
+                    let token = self.peek().expect("operand token was peeked");
+                    let expression = self.primary_token(token);
+                    OperandKind::Expression(expression)
+                }
+                _ => return Err(missing(self)),
+            };
+            let token = self.next().expect("operand token was peeked");
+            return Ok(Operand { kind, text: token.text, span: token.span });
+        }
+
+        match self.peek_kind() {
+            Some(TokenKind::At) => self.label_reference(),
+            Some(TokenKind::Register(number)) => {
+                let kind = OperandKind::Register(*number);
+                let token = self.next().expect("operand token was peeked");
+                Ok(Operand { kind, text: token.text, span: token.span })
+            }
+            Some(TokenKind::Number(_) | TokenKind::Identifier(_) | TokenKind::LeftParen | TokenKind::Minus | TokenKind::Tilde | TokenKind::Bang) => {
+                let expression = self.expression()?;
+                let span = expression.span();
+                Ok(Operand { kind: OperandKind::Expression(expression), text: self.source(span), span })
+            }
+            _ => Err(missing(self)),
+        }
+    }
+
+    /// Number or symbol expression for a single literal or identifier token.
+    fn primary_token(&self, token: &Token) -> Expression {
+        match &token.kind {
+            TokenKind::Number(number) => Expression::Number(number.clone(), token.span),
+            _ => Expression::Symbol(token.text.clone(), token.span),
+        }
+    }
+
+    /// `a || b`, the lowest-precedence level of immediate expressions.
+    fn expression(&mut self) -> Result<Expression, SpannedError> {
+        self.binary(0)
+    }
+
+    /// Binary operators from loosest to tightest binding, like in C.
+    const PRECEDENCE: [&'static [(TokenKind, BinaryOperator)]; 10] = [
+        &[(TokenKind::OrOr, BinaryOperator::LogicalOr)],
+        &[(TokenKind::AndAnd, BinaryOperator::LogicalAnd)],
+        &[(TokenKind::Pipe, BinaryOperator::Or)],
+        &[(TokenKind::Caret, BinaryOperator::Xor)],
+        &[(TokenKind::Ampersand, BinaryOperator::And)],
+        &[(TokenKind::EqualEqual, BinaryOperator::Equal), (TokenKind::NotEqual, BinaryOperator::NotEqual)],
+        &[
+            (TokenKind::Less, BinaryOperator::Less),
+            (TokenKind::LessEqual, BinaryOperator::LessEqual),
+            (TokenKind::Greater, BinaryOperator::Greater),
+            (TokenKind::GreaterEqual, BinaryOperator::GreaterEqual),
+        ],
+        &[(TokenKind::ShiftLeft, BinaryOperator::ShiftLeft), (TokenKind::ShiftRight, BinaryOperator::ShiftRight)],
+        &[(TokenKind::Plus, BinaryOperator::Add), (TokenKind::Minus, BinaryOperator::Subtract)],
+        &[(TokenKind::Star, BinaryOperator::Multiply), (TokenKind::Slash, BinaryOperator::Divide), (TokenKind::Percent, BinaryOperator::Remainder)],
+    ];
+
+    fn binary(&mut self, level: usize) -> Result<Expression, SpannedError> {
+        if level == Self::PRECEDENCE.len() {
+            return self.unary();
+        }
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(&(_, operator)) = Self::PRECEDENCE[level].iter().find(|(kind, _)| self.peek_kind() == Some(kind)) {
+            self.next();
+            let right = self.binary(level + 1)?;
+            let span = left.span().to(right.span());
+            left = Expression::Binary(operator, Box::new(left), Box::new(right), span);
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, SpannedError> {
+        let operator = match self.peek_kind() {
+            Some(TokenKind::Minus) => UnaryOperator::Negate,
+            Some(TokenKind::Tilde) => UnaryOperator::Not,
+            Some(TokenKind::Bang) => UnaryOperator::LogicalNot,
+            _ => return self.primary(),
+        };
+        let start = self.next().expect("unary operator was peeked").span;
+        let operand = self.unary()?;
+        let span = start.to(operand.span());
+        Ok(Expression::Unary(operator, Box::new(operand), span))
+    }
+
+    fn primary(&mut self) -> Result<Expression, SpannedError> {
+        match self.peek().cloned() {
+            Some(token @ Token { kind: TokenKind::Number(_) | TokenKind::Identifier(_), .. }) => {
+                self.next();
+                Ok(self.primary_token(&token))
+            }
+            Some(Token { kind: TokenKind::LeftParen, span: start, .. }) => {
+                self.next();
+                let inner = self.expression()?;
+                let end = self.expect(&TokenKind::RightParen, "`)`")?;
+                Ok(inner.with_span(start.to(end)))
+            }
+            _ => Err(self.unexpected("number, name or `(`")),
+        }
+    }
+
+    /// `@name` in operand position.
+    fn label_reference(&mut self) -> Result<Operand, SpannedError> {
+        let at = self.next().expect("`@` was peeked");
+        match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Identifier(name), span, .. }) => {
+                self.next();
+                Ok(Operand { kind: OperandKind::Label(name.clone()), text: format!("@{}", name), span: at.span.to(span) })
+            }
+            _ => Err(self.unexpected("label name")),
+        }
+    }
+
+    /// `MNEMONIC op, op, op` or `MNEMONIC (op, op, op)`.
+    fn mnemonic_form(&mut self, mnemonic: Mnemonic, mnemonic_span: Span) -> Result<InstructionNode, SpannedError> {
+        let slots = mnemonic.slots();
+        let parenthesized = !slots.is_empty() && self.eat(&TokenKind::LeftParen);
+
+        let mut operands = Vec::with_capacity(slots.len());
+        for (index, slot) in slots.iter().enumerate() {
+            if index > 0 && !self.eat(&TokenKind::Comma) {
+                if self.at_end() || self.peek_kind() == Some(&TokenKind::RightParen) {
+                    let span = self.peek().map_or(self.end, |token| token.span);
+                    return Err(slot.missing().at(span).during(mnemonic));
+                }
+                return Err(self.unexpected("`,`").during(mnemonic));
+            }
+            operands.push(self.operand(*slot).map_err(|err| err.during(mnemonic))?);
+        }
+
+        if parenthesized {
+            self.expect(&TokenKind::RightParen, "`)`").map_err(|err| err.during(mnemonic))?;
+        }
+        self.expect_end().map_err(|err| err.during(mnemonic))?;
+
+        Ok(InstructionNode { mnemonic, mnemonic_span, operands })
+    }
+
+    /// `LI reg1, value` or `LI (reg1, value)`.
+    fn load_immediate(&mut self) -> Result<StatementKind, SpannedError> {
+        self.next();
+        let parenthesized = self.eat(&TokenKind::LeftParen);
+        let reg1 = self.operand(Slot::Reg1)?;
+        if !self.eat(&TokenKind::Comma) {
+            return Err(if self.at_end() { ParseError::MissingImm1.at(self.end) } else { self.unexpected("`,`") });
+        }
+        let value = self.operand(Slot::Imm1)?;
+        if parenthesized {
+            self.expect(&TokenKind::RightParen, "`)`")?;
+        }
+        self.expect_end()?;
+
+        Ok(StatementKind::LoadImmediate(LoadImmediateNode { reg1, value }))
+    }
+
+    /// `reg1 += reg2 + imm`, `reg1 -= ...`, `reg1 *= ...`, `reg1 = ...` and `reg1[low|high] = imm`.
+    ///
+    /// `reg1 = value` without a source register is the `LI` pseudo-instruction.
+    fn infix(&mut self, start: Span) -> Result<StatementKind, SpannedError> {
+        let reg1 = self.operand(Slot::Reg1)?;
+        let operator = self.next().expect("infix operator was peeked");
+        if operator.kind == TokenKind::Equal {
+            self.reversed_operator(operator.span)?;
+        }
+
+        let mnemonic = match operator.kind {
+            TokenKind::PlusEqual => Mnemonic::Add,
+            TokenKind::MinusEqual => Mnemonic::Sub,
+            TokenKind::StarEqual => Mnemonic::Mul,
+            TokenKind::Equal if self.memory_follows(self.position) => return self.load(start, reg1).map(StatementKind::Instruction),
+            TokenKind::Equal if !matches!(self.peek_kind(), Some(TokenKind::Register(_))) && !self.at_end() => {
+                let value = self.operand(Slot::Imm1)?;
+                self.expect_end()?;
+                return Ok(StatementKind::LoadImmediate(LoadImmediateNode { reg1, value }));
+            }
+            TokenKind::Equal => Mnemonic::Mov,
+            _ => return self.set_imm(start, reg1).map(StatementKind::Instruction),
+        };
+
+        let reg2 = self.operand(Slot::Reg2).map_err(|err| err.during(mnemonic))?;
+        if !self.eat(&TokenKind::Plus) {
+            return Err(if self.at_end() { ParseError::MissingImm1.at(self.end) } else { self.unexpected("`+`") }.during(mnemonic));
+        }
+        let imm1 = self.operand(Slot::Imm1).map_err(|err| err.during(mnemonic))?;
+        self.expect_end().map_err(|err| err.during(mnemonic))?;
+
+        Ok(StatementKind::Instruction(InstructionNode { mnemonic, mnemonic_span: start.to(self.end), operands: vec![reg1, reg2, imm1] }))
+    }
+
+    /// Error for `=+`, `=*` or `=-` before a register, written instead of `+=`, `*=` or `-=`.
+    ///
+    /// `reg1 =- 5` is left alone, it loads -5.
+    fn reversed_operator(&self, equal: Span) -> Result<(), SpannedError> {
+        let reversed = match (self.peek_kind(), self.tokens.get(self.position + 1).map(|token| &token.kind)) {
+            (Some(TokenKind::Plus), _) => "+=",
+            (Some(TokenKind::Star), _) => "*=",
+            (Some(TokenKind::Minus), Some(TokenKind::Register(_))) => "-=",
+            _ => return Ok(()),
+        };
+        let span = equal.to(self.peek().expect("operator was peeked").span);
+        Err(ParseError::UnexpectedToken(self.source(span), "`+=`, `-=`, `*=` or `=`")
+            .at(span)
+            .with_help(Some(format!("did you mean `{}`?", reversed))))
+    }
+
+    /// `mem[reg2 + imm]`, `mem[reg2]` or `mem[imm]`, returning the base register and the offset.
+    ///
+    /// A missing offset is 0 and a missing base register is reg0.
+    fn memory(&mut self) -> Result<(Operand, Operand), SpannedError> {
+        self.next();
+        let open = self.expect(&TokenKind::LeftBracket, "`[`")?;
+
+        let (base, offset) = if let Some(TokenKind::Register(_)) = self.peek_kind() {
+            let base = self.operand(Slot::Reg2)?;
+            let offset = if self.eat(&TokenKind::Plus) {
+                self.operand(Slot::Imm1)?
+            } else {
+                let span = base.span.after();
+                Operand { kind: OperandKind::Expression(Expression::Number("0".to_owned(), span)), text: "0".to_owned(), span }
+            };
+            (base, offset)
+        } else {
+            let offset = self.operand(Slot::Imm1)?;
+            let base = Operand { kind: OperandKind::Register(0), text: "reg0".to_owned(), span: open.after() };
+            (base, offset)
+        };
+
+        self.expect(&TokenKind::RightBracket, "`]`")?;
+        Ok((base, offset))
+    }
+
+    /// Rest of `reg1 = mem[...]` after the `=`.
+    fn load(&mut self, start: Span, reg1: Operand) -> Result<InstructionNode, SpannedError> {
+        let result = (|| {
+            let (base, offset) = self.memory()?;
+            self.expect_end()?;
+            Ok((base, offset))
+        })();
+        let (reg2, imm1) = result.map_err(|err: SpannedError| err.during(Mnemonic::Load))?;
+
+        Ok(InstructionNode { mnemonic: Mnemonic::Load, mnemonic_span: start.to(self.end), operands: vec![reg1, reg2, imm1] })
+    }
+
+    /// `mem[...] = reg1`.
+    fn store(&mut self, start: Span) -> Result<InstructionNode, SpannedError> {
+        let result = (|| {
+            let (base, offset) = self.memory()?;
+            self.expect(&TokenKind::Equal, "`=`")?;
+            let value = self.operand(Slot::Reg2)?;
+            self.expect_end()?;
+            Ok(vec![base, offset, value])
+        })();
+        let operands = result.map_err(|err: SpannedError| err.during(Mnemonic::Store))?;
+
+        Ok(InstructionNode { mnemonic: Mnemonic::Store, mnemonic_span: start.to(self.end), operands })
+    }
+
+    /// Rest of `reg1[low] = imm` / `reg1[high] = imm` after the `[`.
+    fn set_imm(&mut self, start: Span, reg1: Operand) -> Result<InstructionNode, SpannedError> {
+        let mnemonic = match self.peek_kind() {
+            Some(TokenKind::Identifier(half)) if half == "low" => Mnemonic::SetImmLow,
+            Some(TokenKind::Identifier(half)) if half == "high" => Mnemonic::SetImmHigh,
+            _ => return Err(self.unexpected("`low` or `high`")),
+        };
+        self.next();
+
+        let result = (|| {
+            self.expect(&TokenKind::RightBracket, "`]`")?;
+            self.expect(&TokenKind::Equal, "`=`")?;
+            let imm1 = self.operand(Slot::Imm1)?;
+            self.expect_end()?;
+            Ok(imm1)
+        })();
+        let imm1 = result.map_err(|err: SpannedError| err.during(mnemonic))?;
+
+        Ok(InstructionNode { mnemonic, mnemonic_span: start.to(self.end), operands: vec![reg1, imm1] })
+    }
+
+    /// Label name after `goto`.
+    fn goto_target(&mut self) -> Result<Operand, SpannedError> {
+        match self.peek().cloned() {
+            Some(Token { kind: TokenKind::Identifier(name), span, text }) => {
+                self.next();
+                Ok(Operand { kind: OperandKind::Label(name), text, span })
+            }
+            _ => Err(ParseError::MissingImm1.at(self.peek().map_or(self.end, |token| token.span))),
+        }
+    }
+
+    /// Advice for an `if` whose comparison is not in parentheses, showing the line with them added.
+    fn parenthesized_condition(&self) -> String {
+        let jump = self.tokens[self.position..]
+            .iter()
+            .position(|token| matches!(&token.kind, TokenKind::Identifier(name) if name == "pc" || name == "goto"))
+            .map(|index| self.position + index);
+        match (jump, self.tokens.last()) {
+            (Some(jump), Some(last)) if jump > self.position => format!(
+                "put the comparison in parentheses: `if ({}) {}`",
+                self.source(self.tokens[self.position].span.to(self.tokens[jump - 1].span)),
+                self.source(self.tokens[jump].span.to(last.span))
+            ),
+            _ => "put the comparison in parentheses, like `if (reg1 < reg2) pc += 1`".to_owned(),
+        }
+    }
+
+    /// `if (reg1 == reg2) pc += imm` and friends with `<`, `!=` and `-=`, or `if (...) goto label`.
+    fn conditional_jump(&mut self) -> Result<InstructionNode, SpannedError> {
+        let start = self.next().expect("`if` was peeked").span;
+
+        if self.peek_kind() != Some(&TokenKind::LeftParen) {
+            return Err(self.unexpected("`(`").with_help(Some(self.parenthesized_condition())));
+        }
+        self.next();
+        let reg1 = self.operand(Slot::Reg1)?;
+        let comparison = match self.peek_kind() {
+            Some(kind @ (TokenKind::EqualEqual | TokenKind::Less | TokenKind::NotEqual)) => kind.clone(),
+            Some(TokenKind::Greater) => {
+                let help = self.tokens.get(self.position + 1).map(|reg2| format!("swap the registers and use `<`: `{} < {}`", reg2.text, reg1.text));
+                return Err(self.unexpected("`==`, `<` or `!=`").with_help(help));
+            }
+            _ => return Err(self.unexpected("`==`, `<` or `!=`")),
+        };
+        self.next();
+        let reg2 = self.operand(Slot::Reg2)?;
+        self.expect(&TokenKind::RightParen, "`)`")?;
+
+        let forward = match self.peek_kind() {
+            Some(TokenKind::Identifier(goto)) if goto == "goto" => None,
+            Some(TokenKind::Identifier(pc)) if pc == "pc" => {
+                self.next();
+                match self.peek_kind() {
+                    Some(TokenKind::PlusEqual) => Some(true),
+                    Some(TokenKind::MinusEqual) => Some(false),
+                    _ => return Err(self.unexpected("`+=` or `-=`")),
+                }
+            }
+            _ => return Err(self.unexpected("`pc` or `goto`")),
+        };
+        self.next();
+
+        let mnemonic = match comparison {
+            TokenKind::EqualEqual => Mnemonic::Jump,
+            TokenKind::Less => Mnemonic::LtJump,
+            _ => Mnemonic::NeqJump,
+        }.with_direction(forward.unwrap_or(true));
+
+        let imm1 = match forward {
+            Some(_) => self.operand(Slot::Imm1),
+            None => self.goto_target(),
+        }.map_err(|err| err.during(mnemonic))?;
+        self.expect_end().map_err(|err| err.during(mnemonic))?;
+
+        Ok(InstructionNode { mnemonic, mnemonic_span: start.to(self.end), operands: vec![reg1, reg2, imm1] })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mnemonic and operand texts of a line that holds one instruction.
+    fn instruction(text: &str) -> (Mnemonic, Vec<String>) {
+        match parse_line(text, 1, 0).map(|statement| statement.kind) {
+            Ok(StatementKind::Instruction(node)) => (node.mnemonic, node.operands.into_iter().map(|operand| operand.text).collect()),
+            other => panic!("`{}` is not an instruction: {:?}", text, other),
+        }
+    }
+
+    /// Code, 1-based column and width of the error a line fails with.
+    fn error(text: &str) -> (&'static str, usize, usize) {
+        let err = parse_line(text, 1, 0).expect_err(text);
+        (err.error.code(), err.span.column, err.span.end - err.span.start)
+    }
+
+    #[test]
+    fn mnemonic_forms() {
+        let cases = [
+            ("NOP", Mnemonic::Nop, &[][..]),
+            ("ADD reg1, reg3, 99", Mnemonic::Add, &["reg1", "reg3", "99"][..]),
+            ("SUB (reg1, reg3, 99)", Mnemonic::Sub, &["reg1", "reg3", "99"]),
+            ("MUL reg1, reg3, 99", Mnemonic::Mul, &["reg1", "reg3", "99"]),
+            ("MOV reg1, reg3, 99", Mnemonic::Mov, &["reg1", "reg3", "99"]),
+            ("LOAD reg1, reg2, 16", Mnemonic::Load, &["reg1", "reg2", "16"]),
+            ("STORE reg1, 16, reg2", Mnemonic::Store, &["reg1", "16", "reg2"]),
+            ("JUMP reg1, reg2, 16", Mnemonic::Jump, &["reg1", "reg2", "16"]),
+            ("REVJUMP reg1, reg2, 16", Mnemonic::RevJump, &["reg1", "reg2", "16"]),
+            ("LTJUMP reg1, reg2, @loop", Mnemonic::LtJump, &["reg1", "reg2", "@loop"]),
+            ("REVLTJUMP reg1, reg2, 16", Mnemonic::RevLtJump, &["reg1", "reg2", "16"]),
+            ("NEQJUMP reg1, reg2, 16", Mnemonic::NeqJump, &["reg1", "reg2", "16"]),
+            ("REVNEQJUMP reg1, reg2, 16", Mnemonic::RevNeqJump, &["reg1", "reg2", "16"]),
+            ("SETIMMLOW reg1, 5", Mnemonic::SetImmLow, &["reg1", "5"]),
+            ("SETIMMHIGH (reg1, 5)", Mnemonic::SetImmHigh, &["reg1", "5"]),
+            ("TELEPORT 17, 17", Mnemonic::Teleport, &["17", "17"]),
+            ("BOMB (100)", Mnemonic::Bomb, &["100"]),
+        ];
+        for (text, mnemonic, operands) in cases {
+            assert_eq!(instruction(text), (mnemonic, operands.iter().map(|operand| operand.to_string()).collect()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn infix_forms() {
+        let cases = [
+            ("reg1 += reg3 + 99", Mnemonic::Add, &["reg1", "reg3", "99"][..]),
+            ("reg1 -= reg3 + 99", Mnemonic::Sub, &["reg1", "reg3", "99"]),
+            ("reg1 *= reg3 + 99", Mnemonic::Mul, &["reg1", "reg3", "99"]),
+            ("reg1 = reg3 + 99", Mnemonic::Mov, &["reg1", "reg3", "99"]),
+            ("reg1 = mem[reg2 + 16]", Mnemonic::Load, &["reg1", "reg2", "16"]),
+            ("reg1 = mem[reg2]", Mnemonic::Load, &["reg1", "reg2", "0"]),
+            ("mem[reg1 + 16] = reg2", Mnemonic::Store, &["reg1", "16", "reg2"]),
+            ("mem[16] = reg2", Mnemonic::Store, &["reg0", "16", "reg2"]),
+            ("if (reg0 == reg1) pc += 3", Mnemonic::Jump, &["reg0", "reg1", "3"]),
+            ("if (reg0 == reg1) pc -= 3", Mnemonic::RevJump, &["reg0", "reg1", "3"]),
+            ("if (reg0 < reg1) pc += 3", Mnemonic::LtJump, &["reg0", "reg1", "3"]),
+            ("if (reg0 != reg1) pc -= 3", Mnemonic::RevNeqJump, &["reg0", "reg1", "3"]),
+            ("if (reg1 < reg2) goto loop", Mnemonic::LtJump, &["reg1", "reg2", "loop"]),
+            ("reg1[low] = 5", Mnemonic::SetImmLow, &["reg1", "5"]),
+            ("reg1[high] = 5", Mnemonic::SetImmHigh, &["reg1", "5"]),
+        ];
+        for (text, mnemonic, operands) in cases {
+            assert_eq!(instruction(text), (mnemonic, operands.iter().map(|operand| operand.to_string()).collect()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn move_with_immediate_keeps_its_source_register() {
+        // The regular expressions used to read this as a plain load of 99.
+        assert_eq!(instruction("reg1 = reg3 + 99"), (Mnemonic::Mov, vec!["reg1".to_owned(), "reg3".to_owned(), "99".to_owned()]));
+        assert!(matches!(parse_line("reg1 = 99", 1, 0).map(|statement| statement.kind), Ok(StatementKind::LoadImmediate(_))));
+    }
+
+    #[test]
+    fn spans_point_into_the_whole_source() {
+        let statement = parse_line("loop: ADD reg1, reg3, 99 ; add", 4, 100).unwrap();
+        assert_eq!(statement.label.map(|label| label.span), Some(Span { start: 100, end: 104, line: 4, column: 1 }));
+        assert_eq!(statement.span, Span { start: 100, end: 124, line: 4, column: 1 });
+        let StatementKind::Instruction(node) = statement.kind else { panic!("not an instruction") };
+        assert_eq!(node.operands[2].span, Span { start: 122, end: 124, line: 4, column: 23 });
+    }
+
+    #[test]
+    fn broken_lines() {
+        assert_eq!(error("+ reg1"), ("E0001", 1, 1));
+        assert_eq!(error("42"), ("E0001", 1, 2));
+        assert_eq!(error("ADD reg1, reg3"), ("E0006", 15, 0));
+        assert_eq!(error("ADD reg1"), ("E0005", 9, 0));
+        assert_eq!(error("ADD (reg1, reg3, 99"), ("E0002", 20, 0));
+        assert_eq!(error("ADD reg1, reg3, 99, 1"), ("E0002", 19, 1));
+        assert_eq!(error("reg1 += reg3 - 99"), ("E0002", 14, 1));
+        assert_eq!(error("reg1 += reg3"), ("E0006", 13, 0));
+        assert_eq!(error("reg1 =+ reg3 + 1"), ("E0002", 6, 2));
+        assert_eq!(error("if reg1 < reg2 pc += 1"), ("E0002", 4, 4));
+        assert_eq!(error("if (reg1 > reg2) pc += 1"), ("E0002", 10, 1));
+        assert_eq!(error("if (reg1 < reg2) pc = 1"), ("E0002", 21, 1));
+        assert_eq!(error("reg1[middle] = 5"), ("E0002", 6, 6));
+        assert_eq!(error("BOMB (1 + )"), ("E0002", 11, 1));
+        assert_eq!(error(".foo 1"), ("E0012", 1, 4));
+    }
+
+    #[test]
+    fn errors_name_the_instruction() {
+        assert_eq!(parse_line("ADD reg1, reg3", 1, 0).unwrap_err().mnemonic, Some(Mnemonic::Add));
+        assert_eq!(parse_line("reg1 *= reg3", 1, 0).unwrap_err().mnemonic, Some(Mnemonic::Mul));
+    }
+}
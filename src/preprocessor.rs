@@ -0,0 +1,368 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::assembler::Options;
+use crate::instructions::{Mnemonic, ParseError, SpannedError};
+use crate::lexer::{tokenize, Span, TokenKind};
+use crate::parser::{parse_line, IncludeNode, MacroCallNode, MacroNode, Statement, StatementKind};
+use crate::suggestions::did_you_mean;
+
+/// How many macro invocations may be nested inside each other, so a macro that invokes itself
+/// is reported instead of expanding forever.
+pub const MAX_MACRO_DEPTH: usize = 16;
+
+/// Names that start other statements and so can never invoke a macro.
+const RESERVED_NAMES: [&str; 4] = ["if", "const", "LI", "mem"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpansionKind {
+    Macro(String),
+    /// An included file, named by the path it was found at.
+    Include(String),
+    /// The 1-based iteration of a `.rep` block.
+    Iteration(usize),
+    /// The counter name and value of a `.for` block's iteration.
+    Loop(String, i64),
+}
+
+/// A source line that other lines were expanded from, such as a macro invocation, an `.include` or a `.rep`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub kind: ExpansionKind,
+    /// Included file the expanding line was written in, `None` for the main source.
+    pub file: Option<String>,
+    /// 1-based line number of the expanding line.
+    pub line: usize,
+    /// The expanding line as written.
+    pub text: String,
+}
+
+/// Describes where the expansion happened, e.g. "macro `copy` invoked on line 7". An include names the
+/// file the `.include` is in, since nested includes are written in different files.
+impl Display for Expansion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ExpansionKind::Macro(name) => write!(f, "macro `{}` invoked on line {}", name, self.line),
+            ExpansionKind::Include(path) => write!(f, "`{}` included from {}:{}", path, self.file.as_deref().unwrap_or("<source>"), self.line),
+            ExpansionKind::Iteration(iteration) => write!(f, "iteration {} of `.rep` on line {}", iteration, self.line),
+            ExpansionKind::Loop(variable, value) => write!(f, "iteration `{} = {}` of `.for` on line {}", variable, value, self.line),
+        }
+    }
+}
+
+/// A line of the program, with the statement parsed from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Included file the line was written in, `None` for the main source.
+    pub file: Option<String>,
+    /// 1-based line number where the text was written, inside the macro body for expanded lines.
+    pub number: usize,
+    /// Byte offset of the line in its file.
+    pub offset: usize,
+    /// The line with macro arguments substituted.
+    pub text: String,
+    pub statement: Result<Statement, SpannedError>,
+    /// What the line was expanded from, innermost first. Empty for lines taken straight from the source.
+    pub expansions: Vec<Expansion>,
+    /// Labels renamed in macro expansions and `.rep` and `.for` iterations, mapped to the names they were
+    /// written with.
+    pub renames: HashMap<String, String>,
+}
+
+/// A line of a macro body before it is parsed.
+#[derive(Debug, Clone)]
+struct RawLine {
+    file: Option<String>,
+    number: usize,
+    /// Byte offset of the line in its file.
+    offset: usize,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    parameters: Vec<String>,
+    /// Labels defined in the body, renamed in every expansion so each gets its own.
+    locals: Vec<String>,
+    body: Vec<RawLine>,
+    /// Where the `.macro` line was written, see [`origin`].
+    origin: Origin,
+}
+
+/// File, line number and the macro invocations and includes of a line, which tell where it was written apart
+/// from the iterations of `.rep` and `.for` blocks it was repeated in.
+type Origin = (Option<String>, usize, Vec<Expansion>);
+
+/// Expands `.macro` definitions, macro invocations and `.include`s one line at a time.
+///
+/// The first pass hands it only the lines it assembles, so a `.macro` or `.include` in an `.if` block that
+/// is left out is never looked at. A macro has to be defined above its first invocation. Problems are
+/// returned in [`SourceLine::statement`] of the line they were found on.
+#[derive(Debug, Default)]
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    /// Number of macro expansions and iterations so far, used to make their labels unique.
+    expansions: usize,
+    /// Directory of the main source and the directories to search for included files.
+    directory: PathBuf,
+    include_paths: Vec<PathBuf>,
+    /// The main source, which no file may include again.
+    main: Option<PathBuf>,
+}
+
+/// Splits `source` into lines and parses them, without expanding anything yet.
+pub fn source_lines(source: &str, file: Option<String>, expansions: &[Expansion]) -> Vec<SourceLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for (index, text) in source.split('\n').enumerate() {
+        let statement = parse_line(text, index + 1, offset);
+        let line = SourceLine { file: file.clone(), number: index + 1, offset, text: text.to_owned(), statement, expansions: expansions.to_vec(), renames: HashMap::new() };
+        lines.push(line);
+        offset += text.len() + 1;
+    }
+    lines
+}
+
+impl Preprocessor {
+    pub fn new(options: &Options) -> Self {
+        let directory = options.path.as_deref().and_then(Path::parent).map(Path::to_path_buf).unwrap_or_default();
+        let main = options.path.as_ref().map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+        Self { directory, include_paths: options.include_paths.clone(), main, ..Self::default() }
+    }
+
+    /// A number no macro expansion or iteration has used yet, to append to the labels defined in it.
+    pub fn next_expansion(&mut self) -> usize {
+        self.expansions += 1;
+        self.expansions
+    }
+
+    /// Expands `line` if it defines or invokes a macro or includes a file.
+    ///
+    /// A definition takes its body off the front of `queue` and puts it back as empty statements, so every
+    /// source line is still accounted for. An invocation or `.include` puts the lines it stands for at the
+    /// front of `queue`, right after `line`.
+    pub fn expand(&mut self, line: &mut SourceLine, queue: &mut VecDeque<SourceLine>) {
+        let result = match &line.statement {
+            Ok(Statement { kind: StatementKind::MacroDefinition(node), .. }) => self.define(&node.clone(), line, queue),
+            Ok(Statement { kind: StatementKind::MacroEnd, span, .. }) => Err(ParseError::UnmatchedEndm.at(*span)),
+            Ok(Statement { kind: StatementKind::MacroCall(call), .. }) => self.invoke(&call.clone(), line, queue),
+            Ok(Statement { kind: StatementKind::Include(node), .. }) => self.include(&node.clone(), line, queue),
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            line.statement = Err(err);
+        }
+    }
+
+    /// Reads the body of the macro started by `line` up to its `.endm` and remembers the macro.
+    fn define(&mut self, node: &MacroNode, line: &SourceLine, queue: &mut VecDeque<SourceLine>) -> Result<(), SpannedError> {
+        let mut error = self.check_definition(node, line);
+
+        // The body ends with the file, macro or iteration the definition is in.
+        let mut body = Vec::new();
+        let mut emptied = Vec::new();
+        let mut terminated = false;
+        while queue.front().is_some_and(|body_line| body_line.expansions == line.expansions) {
+            let mut body_line = queue.pop_front().expect("the queue is not empty");
+            match directive(&body_line) {
+                Some((".endm", _)) => {
+                    body_line.statement = Ok(empty(&body_line));
+                    emptied.push(body_line);
+                    terminated = true;
+                    break;
+                }
+                Some((".macro", span)) => body_line.statement = Err(ParseError::NestedMacro.at(span)),
+                _ => {
+                    body.push(RawLine { file: body_line.file.clone(), number: body_line.number, offset: body_line.offset, text: body_line.text.clone() });
+                    body_line.statement = Ok(empty(&body_line));
+                }
+            }
+            emptied.push(body_line);
+        }
+        prepend(queue, emptied);
+
+        if !terminated && error.is_none() {
+            error = Some(ParseError::UnterminatedMacro(node.name.clone()).at(node.name_span));
+        }
+        if let Some(error) = error {
+            return Err(error);
+        }
+
+        let locals = body.iter().filter_map(|line| local_label(&line.text)).collect();
+        let parameters = node.parameters.iter().map(|parameter| parameter.name.clone()).collect();
+        self.macros.insert(node.name.clone(), Macro { parameters, locals, body, origin: origin(line) });
+        Ok(())
+    }
+
+    fn check_definition(&self, node: &MacroNode, line: &SourceLine) -> Option<SpannedError> {
+        if Mnemonic::from_name(&node.name).is_some() || RESERVED_NAMES.contains(&node.name.as_str()) {
+            return Some(ParseError::ReservedMacroName(node.name.clone()).at(node.name_span));
+        }
+        // A definition inside a `.rep` or `.for` block is met again in every iteration, which is not a duplicate.
+        if self.macros.get(&node.name).is_some_and(|existing| existing.origin != origin(line)) {
+            return Some(ParseError::DuplicateMacro(node.name.clone()).at(node.name_span));
+        }
+        node.parameters.iter().enumerate().find_map(|(index, parameter)| {
+            node.parameters[..index]
+                .iter()
+                .any(|previous| previous.name == parameter.name)
+                .then(|| ParseError::DuplicateParameter(parameter.name.clone()).at(parameter.span))
+        })
+    }
+
+    /// Puts the macro's body with the arguments substituted after the invocation `line`.
+    fn invoke(&mut self, call: &MacroCallNode, line: &SourceLine, queue: &mut VecDeque<SourceLine>) -> Result<(), SpannedError> {
+        let depth = line.expansions.iter().filter(|expansion| matches!(expansion.kind, ExpansionKind::Macro(_))).count();
+        let error = match self.macros.get(&call.name) {
+            None => {
+                // Sorted so the same typo always gets the same suggestion.
+                let mut macros: Vec<&str> = self.macros.keys().map(String::as_str).collect();
+                macros.sort_unstable();
+                let names = Mnemonic::ALL.iter().map(|mnemonic| mnemonic.name()).chain(["LI"]).chain(macros);
+                let help = did_you_mean(&call.name, names);
+                return Err(ParseError::UnknownInstruction.at(call.name_span).with_help(help));
+            }
+            Some(definition) if definition.parameters.len() != call.arguments.len() => {
+                Some(ParseError::MacroArgumentCount(call.name.clone(), definition.parameters.len(), call.arguments.len()))
+            }
+            Some(_) if depth >= MAX_MACRO_DEPTH => Some(ParseError::MacroTooDeep(call.name.clone(), MAX_MACRO_DEPTH)),
+            Some(_) => None,
+        };
+        if let Some(error) = error {
+            return Err(error.at(call.name_span));
+        }
+
+        let number = self.next_expansion();
+        let definition = &self.macros[&call.name];
+
+        let mut substitutions = HashMap::new();
+        // Arguments may name labels renamed around the invocation.
+        let mut renames = line.renames.clone();
+        for local in &definition.locals {
+            let renamed = format!("{}__{}", local, number);
+            substitutions.insert(local.clone(), renamed.clone());
+            renames.insert(renamed, local.clone());
+        }
+        for (parameter, argument) in definition.parameters.iter().zip(&call.arguments) {
+            // Keep an expression argument together wherever the parameter ends up, like `BUF + 4` in `X * 2`.
+            let text = if argument.compound && !argument.text.starts_with('@') { format!("({})", argument.text) } else { argument.text.clone() };
+            substitutions.insert(parameter.clone(), text);
+        }
+
+        let mut expansions = vec![Expansion { kind: ExpansionKind::Macro(call.name.clone()), file: line.file.clone(), line: line.number, text: line.text.clone() }];
+        expansions.extend_from_slice(&line.expansions);
+
+        let body = definition
+            .body
+            .iter()
+            .map(|body_line| {
+                let text = substitute(&body_line.text, &substitutions);
+                let statement = parse_line(&text, body_line.number, body_line.offset);
+                SourceLine { file: body_line.file.clone(), number: body_line.number, offset: body_line.offset, text, statement, expansions: expansions.clone(), renames: renames.clone() }
+            })
+            .collect();
+        prepend(queue, body);
+        Ok(())
+    }
+
+    /// Puts the lines of the file named by the `.include` `line` after it.
+    fn include(&mut self, node: &IncludeNode, line: &SourceLine, queue: &mut VecDeque<SourceLine>) -> Result<(), SpannedError> {
+        let Some(path) = self.resolve(line, &node.path) else {
+            return Err(ParseError::IncludeNotFound(node.path.clone()).at(node.span));
+        };
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        // The files being included right now are the ones the line itself came from.
+        let mut including = line.expansions.iter().filter_map(|expansion| match &expansion.kind {
+            ExpansionKind::Include(name) => Some(fs::canonicalize(name).unwrap_or_else(|_| PathBuf::from(name))),
+            _ => None,
+        });
+        if self.main.as_ref() == Some(&canonical) || including.any(|file| file == canonical) {
+            return Err(ParseError::IncludeCycle(node.path.clone()).at(node.span));
+        }
+        let source = fs::read_to_string(&path).map_err(|err| ParseError::IncludeFailed(node.path.clone(), err.to_string()).at(node.span))?;
+
+        let name = path.display().to_string();
+        let mut expansions = vec![Expansion { kind: ExpansionKind::Include(name.clone()), file: line.file.clone(), line: line.number, text: line.text.clone() }];
+        expansions.extend_from_slice(&line.expansions);
+
+        prepend(queue, source_lines(source.strip_suffix('\n').unwrap_or(&source), Some(name), &expansions));
+        Ok(())
+    }
+
+    /// Finds an included file next to the file including it, or else in the include paths in order.
+    fn resolve(&self, line: &SourceLine, path: &str) -> Option<PathBuf> {
+        let directory = match &line.file {
+            Some(file) => Path::new(file).parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => self.directory.clone(),
+        };
+        std::iter::once(&directory)
+            .chain(&self.include_paths)
+            .map(|directory| directory.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+fn origin(line: &SourceLine) -> Origin {
+    let expansions = line
+        .expansions
+        .iter()
+        .filter(|expansion| matches!(expansion.kind, ExpansionKind::Macro(_) | ExpansionKind::Include(_)))
+        .cloned()
+        .collect();
+    (line.file.clone(), line.number, expansions)
+}
+
+/// Statement for a line that produces nothing by itself, like a macro body line.
+fn empty(line: &SourceLine) -> Statement {
+    Statement { label: None, kind: StatementKind::Empty, span: Span { start: line.offset, end: line.offset, line: line.number, column: 1 } }
+}
+
+/// The directive a line starts with, after an optional label, and its span.
+fn directive(line: &SourceLine) -> Option<(&'static str, Span)> {
+    let tokens = tokenize(&line.text, line.number, line.offset);
+    let first = match (tokens.first().map(|token| &token.kind), tokens.get(1).map(|token| &token.kind)) {
+        (Some(TokenKind::Identifier(_)), Some(TokenKind::Colon)) => tokens.get(2),
+        _ => tokens.first(),
+    }?;
+    match &first.kind {
+        TokenKind::Directive(name) if name == ".endm" => Some((".endm", first.span)),
+        TokenKind::Directive(name) if name == ".macro" => Some((".macro", first.span)),
+        _ => None,
+    }
+}
+
+/// Name of the label defined at the start of `text`, if any.
+fn local_label(text: &str) -> Option<String> {
+    let tokens = tokenize(text, 0, 0);
+    match (tokens.first().map(|token| &token.kind), tokens.get(1).map(|token| &token.kind)) {
+        (Some(TokenKind::Identifier(name)), Some(TokenKind::Colon)) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+/// Puts `lines` at the front of `queue`, in order.
+pub fn prepend(queue: &mut VecDeque<SourceLine>, lines: Vec<SourceLine>) {
+    for line in lines.into_iter().rev() {
+        queue.push_front(line);
+    }
+}
+
+/// Replaces every identifier in `text` that has a substitution, leaving comments alone.
+pub fn substitute(text: &str, substitutions: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut copied = 0;
+
+    for token in tokenize(text, 0, 0) {
+        if let TokenKind::Identifier(name) = &token.kind {
+            if let Some(replacement) = substitutions.get(name) {
+                output.push_str(&text[copied..token.span.start]);
+                output.push_str(replacement);
+                copied = token.span.end;
+            }
+        }
+    }
+
+    output.push_str(&text[copied..]);
+    output
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::disassembler::{to_source, Style};
+use crate::instructions::helpers::format_word;
+use crate::instructions::Instruction;
+use crate::preprocessor::Expansion;
+use crate::symbols::SymbolTable;
+
+/// A word of the program image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Word {
+    Instruction(Instruction),
+    /// Raw data from a directive such as `.word` or `.ascii`.
+    Data([u8; 4]),
+}
+
+impl Word {
+    pub fn encode(&self) -> [u8; 4] {
+        match self {
+            Word::Instruction(instruction) => instruction.encode(),
+            Word::Data(word) => *word,
+        }
+    }
+}
+
+impl Display for Word {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format_word(&self.encode()))
+    }
+}
+
+/// One line of the source together with the words assembled from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Included file the line was written in, `None` for the main source.
+    pub file: Option<String>,
+    /// 1-based line number in the source, inside the macro body for expanded lines.
+    pub number: usize,
+    /// The source line as written, including any comment, with macro arguments substituted.
+    pub text: String,
+    /// Address of the line's first word, or of the next one if it has none.
+    pub address: u32,
+    /// Name of the label defined at the start of the line.
+    pub label: Option<String>,
+    pub words: Vec<Word>,
+    /// Whether the words are the instructions of a pseudo-instruction such as `LI`.
+    pub pseudo: bool,
+    /// Macro invocations and includes the line was expanded from, innermost first.
+    pub expansions: Vec<Expansion>,
+    /// Labels renamed in macro expansions and `.rep` and `.for` iterations, mapped to the names they were
+    /// written with.
+    pub renames: HashMap<String, String>,
+}
+
+/// An assembled program.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    pub lines: Vec<Line>,
+    pub symbols: SymbolTable,
+}
+
+impl Program {
+    pub fn words(&self) -> impl Iterator<Item = &Word> {
+        self.lines.iter().flat_map(|line| &line.words)
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction> {
+        self.words().filter_map(|word| match word {
+            Word::Instruction(instruction) => Some(instruction),
+            Word::Data(_) => None,
+        })
+    }
+
+    /// Encodes every instruction and data word, in program order.
+    pub fn encode(&self) -> Vec<[u8; 4]> {
+        self.words().map(Word::encode).collect()
+    }
+}
+
+/// Formats the program as annotated `.tik` text, keeping blank and comment lines.
+///
+/// Lines without words, such as labels and constants, become comments. A pseudo-instruction
+/// is kept as a comment too, followed by the instructions it expanded into. Data directives
+/// annotate only their first word.
+impl Display for Program {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            if line.pseudo {
+                writeln!(f, "; {}", line.text)?;
+                for word in &line.words {
+                    match word {
+                        Word::Instruction(instruction) => writeln!(f, "{} ; {}", word, to_source(instruction, Style::Infix))?,
+                        Word::Data(_) => writeln!(f, "{}", word)?,
+                    }
+                }
+            } else if let Some((first, rest)) = line.words.split_first() {
+                writeln!(f, "{} ; {}", first, line.text)?;
+                for word in rest {
+                    writeln!(f, "{}", word)?;
+                }
+            } else if line.text.trim().is_empty() {
+                writeln!(f)?;
+            } else if line.text.trim_start().starts_with(';') {
+                writeln!(f, "{}", line.text)?;
+            } else {
+                writeln!(f, "; {}", line.text)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use crate::instructions::helpers::signed_immediate;
+use crate::instructions::{ParseError, Slot, SpannedError};
+use crate::lexer::Span;
+use crate::parser::{parse_line, RepeatNode, Statement, StatementKind};
+use crate::preprocessor::{prepend, substitute, Expansion, ExpansionKind, Preprocessor, SourceLine};
+use crate::symbols::Context;
+
+/// Most lines all `.rep` and `.for` blocks of a program may expand to together, so a mistyped
+/// count is reported instead of using up all memory. An empty block counts a line for every iteration, so
+/// it can not spin through billions of them either. The words the lines take are limited by the address
+/// space instead, since a single line like `.zero` can take many.
+pub const MAX_REPEATED_LINES: usize = 65536;
+
+/// Replaces the block opened by `opening` at the front of `queue` with its iterations, counting the lines they
+/// add in `repeated` and numbering them with `preprocessor`.
+///
+/// A block that can not be repeated, because its count is wrong or one of its lines is broken, is assembled once
+/// as it is so its lines are still checked.
+pub fn repeat(node: &RepeatNode, opening: &SourceLine, span: Span, queue: &mut VecDeque<SourceLine>, repeated: &mut usize, preprocessor: &mut Preprocessor, context: &Context) -> Result<(), SpannedError> {
+    let Some((body, mut end)) = take_body(queue, opening) else {
+        return Err(ParseError::UnterminatedRepeat(node.directive().to_owned()).at(span));
+    };
+    // The `.endr` keeps its label, which names the address after the block.
+    end.statement = end.statement.map(|statement| Statement { kind: StatementKind::Empty, ..statement });
+    queue.push_front(end);
+
+    if body.iter().any(|line| line.statement.is_err()) {
+        prepend(queue, body);
+        return Ok(());
+    }
+    let iterations = match iterations(node, context) {
+        Ok(iterations) => iterations,
+        Err(err) => {
+            prepend(queue, body);
+            return Err(err);
+        }
+    };
+
+    let previous = *repeated;
+    *repeated = repeated.saturating_add(body.len().max(1).saturating_mul(count(&iterations)));
+    if *repeated > MAX_REPEATED_LINES {
+        prepend(queue, body);
+        // Only the block that crosses the limit is reported, the ones after it are just not repeated.
+        if previous > MAX_REPEATED_LINES {
+            return Ok(());
+        }
+        let count = match node {
+            RepeatNode::Rep(count) | RepeatNode::For { end: count, .. } => count,
+        };
+        return Err(ParseError::RepeatTooLarge(count.text.clone(), MAX_REPEATED_LINES).at(count.span));
+    }
+    prepend(queue, expand(node, opening, &body, iterations, preprocessor));
+    Ok(())
+}
+
+/// Values of the counter in every iteration of `node`.
+fn iterations(node: &RepeatNode, context: &Context) -> Result<Range<i64>, SpannedError> {
+    match node {
+        RepeatNode::Rep(count) => {
+            let value = signed_immediate(count, Slot::Imm1, 0, i64::from(u32::MAX), context)?;
+            if value > MAX_REPEATED_LINES as i64 {
+                return Err(ParseError::RepeatTooLarge(count.text.clone(), MAX_REPEATED_LINES).at(count.span));
+            }
+            Ok(0..value)
+        }
+        RepeatNode::For { start, end, .. } => {
+            let start = signed_immediate(start, Slot::Imm1, -(1 << 31), i64::from(u32::MAX), context)?;
+            let end = signed_immediate(end, Slot::Imm1, -(1 << 31), i64::from(u32::MAX), context)?;
+            Ok(start..end)
+        }
+    }
+}
+
+/// Takes the lines of the block opened by `opening` out of `queue`, along with its `.endr`.
+///
+/// Blocks nested inside are taken whole. `None` if the block is never closed in the same file or macro,
+/// which leaves `queue` as it was.
+fn take_body(queue: &mut VecDeque<SourceLine>, opening: &SourceLine) -> Option<(Vec<SourceLine>, SourceLine)> {
+    let mut depth = 0;
+    let end = queue.iter().position(|line| {
+        let kind = line.statement.as_ref().ok().map(|statement| &statement.kind).filter(|_| line.expansions == opening.expansions);
+        match kind {
+            Some(StatementKind::Repeat(_)) => depth += 1,
+            Some(StatementKind::RepeatEnd) if depth == 0 => return true,
+            Some(StatementKind::RepeatEnd) => depth -= 1,
+            _ => {}
+        }
+        false
+    })?;
+
+    let body = queue.drain(..end).collect();
+    let end = queue.pop_front().expect("`.endr` was found");
+    Some((body, end))
+}
+
+/// The lines of `body` once for every value in `iterations`, each marked with the iteration it belongs to.
+///
+/// Labels defined in the block get a number appended, like `next__2`, so each iteration has its own. The numbers
+/// are shared with macro expansions, so no two blocks or macros can end up with the same name.
+fn expand(node: &RepeatNode, opening: &SourceLine, body: &[SourceLine], iterations: Range<i64>, preprocessor: &mut Preprocessor) -> Vec<SourceLine> {
+    let labels: Vec<&str> = body
+        .iter()
+        .filter_map(|line| line.statement.as_ref().ok()?.label.as_ref())
+        .map(|label| label.name.as_str())
+        .collect();
+
+    let mut output = Vec::with_capacity(body.len() * count(&iterations));
+    for (index, value) in iterations.enumerate() {
+        let number = preprocessor.next_expansion();
+        let renamed: Vec<(&str, String)> = labels.iter().map(|label| (*label, format!("{}__{}", label, number))).collect();
+        let mut substitutions: HashMap<String, String> = renamed.iter().map(|(label, renamed)| (label.to_string(), renamed.clone())).collect();
+        let kind = match node {
+            RepeatNode::Rep(_) => ExpansionKind::Iteration(index + 1),
+            RepeatNode::For { variable, .. } => {
+                // Keep a negative counter together wherever it ends up, like `i` in `X - i`.
+                let text = if value < 0 { format!("({})", value) } else { value.to_string() };
+                substitutions.insert(variable.clone(), text);
+                ExpansionKind::Loop(variable.clone(), value)
+            }
+        };
+        let iteration = Expansion { kind, file: opening.file.clone(), line: opening.number, text: opening.text.clone() };
+
+        for line in body {
+            let text = substitute(&line.text, &substitutions);
+            let statement = parse_line(&text, line.number, line.offset);
+            let inner = line.expansions.len().saturating_sub(opening.expansions.len());
+            let mut expansions = line.expansions[..inner].to_vec();
+            expansions.push(iteration.clone());
+            expansions.extend_from_slice(&opening.expansions);
+            // A label renamed by an enclosing block keeps the name it was written with.
+            let mut renames = line.renames.clone();
+            for (label, renamed) in &renamed {
+                let written = line.renames.get(*label).map_or(*label, String::as_str);
+                renames.insert(renamed.clone(), written.to_owned());
+            }
+            output.push(SourceLine { text, statement, expansions, renames, ..line.clone() });
+        }
+    }
+    output
+}
+
+fn count(iterations: &Range<i64>) -> usize {
+    (iterations.end - iterations.start).max(0) as usize
+}
@@ -0,0 +1,82 @@
+use std::fmt::{Display, Formatter};
+use crate::json;
+use crate::symbols::{SymbolKind, SymbolTable};
+
+/// A symbol as read back from a symbol map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapEntry {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Word address of a label or value of a constant.
+    pub value: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolMapError {
+    /// A `.sym` line that is not `kind name value`.
+    InvalidLine(usize, String),
+}
+
+impl Display for SymbolMapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SymbolMapError::InvalidLine(line, text) => write!(f, "line {} is not `kind name value`: {}", line, text),
+        }
+    }
+}
+
+impl std::error::Error for SymbolMapError {}
+
+/// Formats `symbols` as a `.sym` file, one `kind name value` line per symbol in definition order.
+///
+/// Values are decimal, labels hold word addresses. Lines starting with `;` are comments.
+pub fn to_sym(symbols: &SymbolTable) -> String {
+    let mut output = String::from("; sopt symbol map: kind name value\n");
+    for symbol in symbols.iter() {
+        output.push_str(&format!("{} {} {}\n", symbol.kind.name(), symbol.name, symbol.value));
+    }
+    output
+}
+
+/// Formats `symbols` as JSON, `{"symbols": [{"name": ..., "kind": "label" | "constant", "value": ...}]}`.
+pub fn to_json(symbols: &SymbolTable) -> String {
+    let entries: Vec<String> = symbols
+        .iter()
+        .map(|symbol| {
+            format!(
+                "    {{\"name\": {}, \"kind\": {}, \"value\": {}}}",
+                json::string(&symbol.name),
+                json::string(symbol.kind.name()),
+                symbol.value
+            )
+        })
+        .collect();
+
+    if entries.is_empty() {
+        return "{\"symbols\": []}\n".to_owned();
+    }
+    format!("{{\"symbols\": [\n{}\n]}}\n", entries.join(",\n"))
+}
+
+/// Reads a `.sym` file written by [`to_sym`].
+pub fn parse_sym(text: &str) -> Result<Vec<MapEntry>, SymbolMapError> {
+    let mut entries = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let invalid = || SymbolMapError::InvalidLine(index + 1, line.to_owned());
+        let mut fields = line.split_whitespace();
+        let (Some(kind), Some(name), Some(value), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            return Err(invalid());
+        };
+        let kind = SymbolKind::from_name(kind).ok_or_else(invalid)?;
+        let value = value.parse().map_err(|_| invalid())?;
+        entries.push(MapEntry { name: name.to_owned(), kind, value });
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use crate::instructions::{ParseError, SpannedError};
+use crate::lexer::Span;
+use crate::parser::{Operand, OperandKind};
+use crate::suggestions::did_you_mean;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Address of the instruction following a `name:` definition.
+    Label,
+    /// Value given by `.equ NAME, value` or `const NAME = value`.
+    Constant,
+}
+
+impl SymbolKind {
+    pub fn from_name(name: &str) -> Option<SymbolKind> {
+        match name {
+            "label" => Some(SymbolKind::Label),
+            "constant" => Some(SymbolKind::Constant),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SymbolKind::Label => "label",
+            SymbolKind::Constant => "constant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub value: i64,
+    /// Where the symbol was defined.
+    pub span: Span,
+}
+
+/// Labels and constants of a program, kept in definition order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    index: HashMap<String, usize>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn define(&mut self, name: &str, kind: SymbolKind, value: i64, span: Span) -> Result<(), SpannedError> {
+        if self.index.contains_key(name) {
+            return Err(ParseError::DuplicateSymbol(name.to_owned()).at(span));
+        }
+        self.index.insert(name.to_owned(), self.symbols.len());
+        self.symbols.push(Symbol { name: name.to_owned(), kind, value, span });
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.index.get(name).map(|&index| &self.symbols[index])
+    }
+
+    /// Error for using `name`, which is not defined, suggesting a defined name it may be a typo of.
+    pub fn undefined(&self, name: &str, span: Span) -> SpannedError {
+        let help = did_you_mean(name, self.symbols.iter().map(|symbol| symbol.name.as_str()));
+        ParseError::UndefinedSymbol(name.to_owned()).at(span).with_help(help)
+    }
+
+    /// Value of an expression operand, `None` if the operand is not a valid expression.
+    pub fn value(&self, operand: &Operand) -> Result<Option<i64>, SpannedError> {
+        match &operand.kind {
+            OperandKind::Expression(expression) => expression.evaluate(self),
+            OperandKind::Register(_) | OperandKind::Label(_) => Ok(None),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// What lowering an instruction needs to know beyond its own operands.
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    pub symbols: &'a SymbolTable,
+    /// Address of the instruction being lowered.
+    pub address: u32,
+}